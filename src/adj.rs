@@ -1,5 +1,114 @@
 use crate::prelude::*;
 use bevy::prelude::*;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// Implementing this trait for your own chunk storage lets the library look up voxels in the
+/// chunks surrounding the one being meshed, without copying them into fixed-size arrays.
+/// It is used for culling the faces on the edges of a chunk, `introduce_adjacent_chunks` and
+/// Smooth Lighting.
+pub trait ChunkNeighbors<T> {
+    /// Returns the voxel at `index` (same 1d layout as the main grid) of the chunk that is in
+    /// `direction` from the main chunk, or None if that chunk isn't available.
+    fn get_voxel(&self, direction: Direction, index: usize) -> Option<T>;
+}
+
+impl<T, F> ChunkNeighbors<T> for F
+where
+    F: Fn(Direction, usize) -> Option<T>,
+{
+    fn get_voxel(&self, direction: Direction, index: usize) -> Option<T> {
+        self(direction, index)
+    }
+}
+
+/// The chunks surrounding the main chunk as slices, indexed by [`Direction`].
+/// A missing chunk is treated as if it wasn't loaded.
+pub struct AdjacentChunks<'a, T> {
+    pub chunks: [Option<&'a [T]>; 8],
+}
+
+impl<'a, T> AdjacentChunks<'a, T> {
+    /// No adjacent chunks.
+    pub fn new() -> Self {
+        AdjacentChunks { chunks: [None; 8] }
+    }
+
+    /// Set the chunk in `direction` from the main chunk.
+    pub fn with(mut self, direction: Direction, chunk: &'a [T]) -> Self {
+        self.chunks[Into::<usize>::into(direction)] = Some(chunk);
+        self
+    }
+}
+
+impl<T> Default for AdjacentChunks<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy> ChunkNeighbors<T> for AdjacentChunks<'_, T> {
+    fn get_voxel(&self, direction: Direction, index: usize) -> Option<T> {
+        self.chunks[Into::<usize>::into(direction)].and_then(|c| c.get(index).copied())
+    }
+}
+
+/// Same as [`AdjacentChunks`], for chunks that are shared between threads. Lock them with
+/// [`AdjacentArcChunks::read`] to look up their voxels.
+pub struct AdjacentArcChunks<'a, T, const N: usize> {
+    pub chunks: [Option<&'a Arc<RwLock<[T; N]>>>; 8],
+}
+
+impl<'a, T, const N: usize> AdjacentArcChunks<'a, T, N> {
+    /// No adjacent chunks.
+    pub fn new() -> Self {
+        AdjacentArcChunks { chunks: [None; 8] }
+    }
+
+    /// Set the chunk in `direction` from the main chunk.
+    pub fn with(mut self, direction: Direction, chunk: &'a Arc<RwLock<[T; N]>>) -> Self {
+        self.chunks[Into::<usize>::into(direction)] = Some(chunk);
+        self
+    }
+
+    /// Lock all the chunks for reading, the voxels are then looked up through the guards without
+    /// locking the chunks again. Returns an error if the lock of one of the chunks is poisoned.
+    pub fn read(&self) -> Result<AdjacentChunkGuards<'a, T, N>, MeshemError> {
+        let mut guards = AdjacentChunkGuards {
+            guards: std::array::from_fn(|_| None),
+        };
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if let Some(chunk) = chunk {
+                let direction = Direction::from(i as u8 + 1);
+                guards.guards[i] = Some(
+                    chunk
+                        .read()
+                        .map_err(|_| MeshemError::PoisonedChunk(direction))?,
+                );
+            }
+        }
+        Ok(guards)
+    }
+}
+
+impl<T, const N: usize> Default for AdjacentArcChunks<'_, T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The chunks of [`AdjacentArcChunks`], locked for reading (see [`AdjacentArcChunks::read`]),
+/// indexed by [`Direction`].
+pub struct AdjacentChunkGuards<'a, T, const N: usize> {
+    pub guards: [Option<RwLockReadGuard<'a, [T; N]>>; 8],
+}
+
+impl<T: Copy, const N: usize> ChunkNeighbors<T> for AdjacentChunkGuards<'_, T, N> {
+    fn get_voxel(&self, direction: Direction, index: usize) -> Option<T> {
+        self.guards[Into::<usize>::into(direction)]
+            .as_ref()
+            .and_then(|c| c.get(index).copied())
+    }
+}
 
 /// This function will iron out any problems caused by generating two chunks that are adjacent to
 /// each other seperatly. For example, it will cull the unneeded vertices between them, and fix
@@ -9,33 +118,103 @@ use bevy::prelude::*;
 /// main_md: the metadata of the mesh to change
 /// connection_side: from the POV of the main mesh, where is the adjacent mesh?
 /// adjacent_chunk_grid: the grid of the chunk to introduce
+/// Returns the parts of the main mesh that changed, like [`update_mesh`].
+/// Panics if the chunks couldn't be introduced, see [`try_introduce_adjacent_chunks`].
 pub fn introduce_adjacent_chunks<T: std::fmt::Debug + Sized + Copy>(
     reg: &impl VoxelRegistry<Voxel = T>,
     main_mesh: &mut Mesh,
     main_md: &mut MeshMD<T>,
     connection_side: Face,
     adjacent_chunk_grid: &[T],
-) {
-    assert_eq!(
-        adjacent_chunk_grid.len(),
-        main_md.vivi.vivi.len(),
-        "Cannot introduce chunks with different sizes to each other"
-    );
+) -> DirtyRanges {
+    match try_introduce_adjacent_chunks(
+        reg,
+        main_mesh,
+        main_md,
+        connection_side,
+        adjacent_chunk_grid,
+    ) {
+        Ok(dirty) => dirty,
+        Err(e) => panic!("Couldn't introduce adjacent chunks: {}", e),
+    }
+}
+
+/// Same as [`introduce_adjacent_chunks`], but returns an error instead of panicking.
+pub fn try_introduce_adjacent_chunks<T: std::fmt::Debug + Sized + Copy>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    main_mesh: &mut Mesh,
+    main_md: &mut MeshMD<T>,
+    connection_side: Face,
+    adjacent_chunk_grid: &[T],
+) -> Result<DirtyRanges, MeshemError> {
+    MeshemError::check_grid_size(main_md.dims, adjacent_chunk_grid.len())?;
+    introduce_adjacent_chunks_with_neighbors(
+        reg,
+        main_mesh,
+        main_md,
+        connection_side,
+        &|_, i: usize| Some(adjacent_chunk_grid[i]),
+    )
+}
+
+/// Same as [`try_introduce_adjacent_chunks`], but the adjacent chunk is looked up through a
+/// [`ChunkNeighbors`] implementation, in the direction of `connection_side`.
+pub fn introduce_adjacent_chunks_with_neighbors<T: std::fmt::Debug + Sized + Copy>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    main_mesh: &mut Mesh,
+    main_md: &mut MeshMD<T>,
+    connection_side: Face,
+    neighbors: &impl ChunkNeighbors<T>,
+) -> Result<DirtyRanges, MeshemError> {
     let dims = main_md.dims;
     for index in iter_faces_of_chunk(dims, connection_side) {
-        let adj_voxel_index = get_neigbhor_across_chunk_safe(dims, index, connection_side);
-        let adj_voxel_index = if adj_voxel_index.is_some() {
-            adj_voxel_index.unwrap()
-        } else {
+        let Some(adj_voxel_index) = get_neigbhor_across_chunk_safe(dims, index, connection_side)
+        else {
+            continue;
+        };
+        let Some(adj_voxel) =
+            neighbors.get_voxel(Direction::from(connection_side), adj_voxel_index)
+        else {
             continue;
         };
 
-        let adj_voxel = adjacent_chunk_grid[adj_voxel_index];
-        if reg.is_covering(&adj_voxel, connection_side.opposite()) {
+        if is_covering_oriented(reg, &adj_voxel, connection_side.opposite()) {
             let mut tmp = [None; 6];
             tmp[connection_side as usize] = Some(adj_voxel);
             main_md.log(VoxelChange::CullFaces, index, adj_voxel, tmp)
         }
     }
-    update_mesh(main_mesh, main_md, reg);
+    try_update_mesh(main_mesh, main_md, reg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arc_chunks_are_read_once_and_poisoned_locks_are_errors() {
+        let north = Arc::new(RwLock::new([1u16, 2, 3]));
+        let west = Arc::new(RwLock::new([4u16, 5, 6]));
+        let chunks = AdjacentArcChunks::new()
+            .with(Direction::North, &north)
+            .with(Direction::West, &west);
+        {
+            let guards = chunks.read().unwrap();
+            assert_eq!(guards.get_voxel(Direction::North, 2), Some(3));
+            assert_eq!(guards.get_voxel(Direction::West, 0), Some(4));
+            assert_eq!(guards.get_voxel(Direction::West, 3), None);
+            assert_eq!(guards.get_voxel(Direction::South, 0), None);
+        }
+
+        let poison = west.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poison.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(matches!(
+            chunks.read(),
+            Err(MeshemError::PoisonedChunk(Direction::West))
+        ));
+    }
 }
@@ -0,0 +1,182 @@
+//! This module contains animated textures (water, lava, portals...). The frames of an animation
+//! are tiles of the atlas, and every vertex carries a descriptor of the animation of its face, so
+//! [`AnimatedVoxelMaterial`] advances the frames on the GPU, without regenerating the meshes.
+use crate::prelude::*;
+use bevy::asset::embedded_asset;
+use bevy::mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef};
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, SpecializedMeshPipelineError, VertexFormat,
+};
+use bevy::shader::ShaderRef;
+use serde::{Deserialize, Serialize};
+
+/// The animation of the face of each vertex, `[frames, frame time, u step, v step]`. The frame
+/// shown at time `t` (in seconds) is `floor(t / frame time) % frames`, and each frame is one
+/// step further in UV space than the one before it. Faces that aren't animated have one frame.
+/// Add it to `VoxelRegistry::all_attributes` when meshing voxels animated with
+/// [`animate_voxel_faces`].
+pub const ATTRIBUTE_TEXTURE_ANIMATION: MeshVertexAttribute = MeshVertexAttribute::new(
+    "Vertex_TextureAnimation",
+    988_540_919,
+    VertexFormat::Float32x4,
+);
+
+const SHADER_PATH: &str = "embedded://bevy_meshem/animation.wgsl";
+
+// The descriptor of a face that isn't animated.
+const STILL: [f32; 4] = [1.0, 1.0, 0.0, 0.0];
+
+/// An animated texture: the tiles of its frames in the atlas, in order, and how long each frame
+/// is shown (in seconds). The frames must be evenly spaced in the atlas, for example next to
+/// each other in a row.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextureAnimation {
+    pub frames: Vec<[u32; 2]>,
+    pub frame_time: f32,
+}
+
+impl TextureAnimation {
+    pub fn new(frames: Vec<[u32; 2]>, frame_time: f32) -> Self {
+        TextureAnimation { frames, frame_time }
+    }
+
+    /// An animation of `frames` tiles next to each other in a row of the atlas, starting at
+    /// `first`.
+    pub fn row(first: [u32; 2], frames: u32, frame_time: f32) -> Self {
+        TextureAnimation {
+            frames: (0..frames).map(|i| [first[0] + i, first[1]]).collect(),
+            frame_time,
+        }
+    }
+
+    // The value of `ATTRIBUTE_TEXTURE_ANIMATION` for the faces animated by it, None if it has no
+    // frames, the frames aren't evenly spaced, or the frame time isn't positive.
+    fn descriptor(&self, texture_atlas_dims: [u32; 2]) -> Option<[f32; 4]> {
+        let first = self.frames.first()?;
+        if self.frame_time.is_nan() || self.frame_time <= 0.0 {
+            return None;
+        }
+        let step =
+            |a: &[u32; 2], b: &[u32; 2]| [b[0] as i64 - a[0] as i64, b[1] as i64 - a[1] as i64];
+        let first_step = self
+            .frames
+            .get(1)
+            .map_or([0, 0], |second| step(first, second));
+        if self
+            .frames
+            .windows(2)
+            .any(|w| step(&w[0], &w[1]) != first_step)
+        {
+            return None;
+        }
+        Some([
+            self.frames.len() as f32,
+            self.frame_time,
+            first_step[0] as f32 / texture_atlas_dims[0] as f32,
+            first_step[1] as f32 / texture_atlas_dims[1] as f32,
+        ])
+    }
+}
+
+/// Add an [`ATTRIBUTE_TEXTURE_ANIMATION`] to `cube`, a voxel mesh generated with
+/// `generate_voxel_mesh`, animating the given faces, the rest of the faces stay still. The
+/// animated faces should be textured with the first frame of their animation.
+/// Shapes cut out of the cube with [`generate_shape_mesh`] keep the animations, and so do
+/// rotated and UV transformed voxels.
+pub fn animate_voxel_faces(
+    cube: &mut Mesh,
+    texture_atlas_dims: [u32; 2],
+    animations: &[(Face, &TextureAnimation)],
+) -> Result<(), MeshemError> {
+    let vertices = cube.count_vertices();
+    if vertices != 24 {
+        return Err(MeshemError::UnexpectedVertexCount {
+            expected: 24,
+            found: vertices,
+        });
+    }
+    let mut descriptors = [STILL; 6];
+    for (face, animation) in animations {
+        descriptors[*face as usize] = animation
+            .descriptor(texture_atlas_dims)
+            .ok_or(MeshemError::InvalidTextureAnimation(*face))?;
+    }
+    // The vertices are sorted by face, 4 vertices for each face.
+    cube.insert_attribute(
+        ATTRIBUTE_TEXTURE_ANIMATION,
+        (0..24)
+            .map(|v| descriptors[v / 4])
+            .collect::<Vec<[f32; 4]>>(),
+    );
+    Ok(())
+}
+
+/// A material that textures voxel meshes from an atlas, and plays the animations of their
+/// [`ATTRIBUTE_TEXTURE_ANIMATION`] on the GPU (using the time of the `GlobalsUniform`, which
+/// wraps around every hour). It's unlit, the vertex colors (if the mesh has any) are multiplied
+/// into the texture, so Smooth Lighting still applies.
+/// Add [`AnimatedVoxelPlugin`] to use it.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct AnimatedVoxelMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub atlas: Handle<Image>,
+    pub alpha_mode: AlphaMode,
+}
+
+impl AnimatedVoxelMaterial {
+    pub fn new(atlas: Handle<Image>) -> Self {
+        AnimatedVoxelMaterial {
+            atlas,
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+}
+
+impl Material for AnimatedVoxelMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let mut attributes = vec![
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            ATTRIBUTE_TEXTURE_ANIMATION.at_shader_location(2),
+        ];
+        if layout.0.contains(Mesh::ATTRIBUTE_COLOR) {
+            attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(3));
+            descriptor.vertex.shader_defs.push("VERTEX_COLORS".into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("VERTEX_COLORS".into());
+            }
+        }
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&attributes)?];
+        Ok(())
+    }
+}
+
+/// Embeds the shader of [`AnimatedVoxelMaterial`], and registers the material.
+pub struct AnimatedVoxelPlugin;
+
+impl Plugin for AnimatedVoxelPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "animation.wgsl");
+        app.add_plugins(MaterialPlugin::<AnimatedVoxelMaterial>::default());
+    }
+}
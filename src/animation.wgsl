@@ -0,0 +1,49 @@
+// The shader of `AnimatedVoxelMaterial`. It offsets the UVs of each vertex to the current frame
+// of the animation given by its `ATTRIBUTE_TEXTURE_ANIMATION`. It's unlit, the vertex colors
+// (which hold the Smooth Lighting, if it's applied) are multiplied into the texture.
+#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
+#import bevy_pbr::mesh_view_bindings::globals
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(0) var atlas_texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(1) var atlas_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    // [frames, frame time, u step, v step]
+    @location(2) animation: vec4<f32>,
+#ifdef VERTEX_COLORS
+    @location(3) color: vec4<f32>,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(
+        get_world_from_local(vertex.instance_index),
+        vec4<f32>(vertex.position, 1.0),
+    );
+    // All the vertices of a face have the same animation, so they're on the same frame.
+    let frames = max(u32(vertex.animation.x), 1u);
+    let frame = u32(floor(globals.time / max(vertex.animation.y, 0.0001))) % frames;
+    out.uv = vertex.uv + vertex.animation.zw * f32(frame);
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#else
+    out.color = vec4<f32>(1.0);
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(atlas_texture, atlas_sampler, in.uv) * in.color;
+}
@@ -0,0 +1,170 @@
+//! This module contains the texture atlas builder. It packs the textures of the blocks into one
+//! atlas image, and keeps track of where each of them ended up, so the tiles can be passed to
+//! `generate_voxel_mesh` by name instead of by hand-picked coordinates.
+use crate::prelude::*;
+use bevy::asset::RenderAssetUsages;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use std::borrow::Cow;
+
+/// Packs textures of equal size into a [`VoxelAtlas`]. Every tile gets a border of `padding`
+/// pixels, filled by extruding its edges (so sampling near the edge of a tile, or from a smaller
+/// mip level, doesn't bleed the neighboring tiles in).
+/// The textures must be RGBA8 (or convertible to it), and their data must be available on the
+/// CPU, images loaded with `RenderAssetUsages::RENDER_WORLD` only are dropped from the main world
+/// once they are uploaded.
+#[derive(Default)]
+pub struct AtlasBuilder {
+    padding: u32,
+    tiles: Vec<(String, Image)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(padding: u32) -> Self {
+        AtlasBuilder {
+            padding,
+            tiles: vec![],
+        }
+    }
+
+    /// Add a tile to the atlas, replacing the tile with the same name if there is one.
+    pub fn with(mut self, name: impl Into<String>, image: Image) -> Self {
+        self.add(name, image);
+        self
+    }
+
+    /// Same as [`AtlasBuilder::with`], but doesn't take ownership of the builder.
+    pub fn add(&mut self, name: impl Into<String>, image: Image) {
+        let name = name.into();
+        match self.tiles.iter_mut().find(|(n, _)| *n == name) {
+            Some(tile) => tile.1 = image,
+            None => self.tiles.push((name, image)),
+        }
+    }
+
+    /// Pack the tiles into an atlas, as close to a square as possible, in the order they were
+    /// added (left to right, top to bottom). Errors refer to the tiles by that order.
+    pub fn build(&self) -> Result<VoxelAtlas, MeshemError> {
+        let Some((_, first)) = self.tiles.first() else {
+            return Err(MeshemError::EmptyAtlas);
+        };
+        let tile_size = [first.width(), first.height()];
+        let p = self.padding;
+        let cell = [tile_size[0] + 2 * p, tile_size[1] + 2 * p];
+        let columns = (self.tiles.len() as f32).sqrt().ceil() as u32;
+        let rows = (self.tiles.len() as u32).div_ceil(columns);
+        let width = columns * cell[0];
+        let height = rows * cell[1];
+
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        let mut tiles = HashMap::new();
+        for (k, (name, image)) in self.tiles.iter().enumerate() {
+            let found = [image.width(), image.height()];
+            if found.contains(&0) {
+                return Err(MeshemError::EmptyTile(k));
+            }
+            if found != tile_size {
+                return Err(MeshemError::TileSizeMismatch {
+                    tile: k,
+                    expected: tile_size,
+                    found,
+                });
+            }
+            let pixels = rgba8_pixels(image, k)?;
+            let tile = [k as u32 % columns, k as u32 / columns];
+            let origin = [tile[0] * cell[0], tile[1] * cell[1]];
+            for y in 0..cell[1] {
+                // Pixels in the border take the value of the closest pixel of the tile.
+                let sy = y.saturating_sub(p).min(tile_size[1] - 1);
+                for x in 0..cell[0] {
+                    let sx = x.saturating_sub(p).min(tile_size[0] - 1);
+                    let src = ((sy * tile_size[0] + sx) * 4) as usize;
+                    let dst = (((origin[1] + y) * width + origin[0] + x) * 4) as usize;
+                    data[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+                }
+            }
+            tiles.insert(name.clone(), tile);
+        }
+
+        Ok(VoxelAtlas {
+            image: Image::new(
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::default(),
+            ),
+            dims: [columns, rows],
+            // The padding is relative to the size of a cell, it's taken from the shorter side so
+            // the UVs never reach past the border.
+            padding: p as f32 / (tile_size[0].min(tile_size[1]) + 2 * p) as f32,
+            tiles,
+        })
+    }
+}
+
+/// A texture atlas built by [`AtlasBuilder`].
+pub struct VoxelAtlas {
+    /// The atlas itself, Rgba8UnormSrgb.
+    pub image: Image,
+    dims: [u32; 2],
+    padding: f32,
+    tiles: HashMap<String, [u32; 2]>,
+}
+
+impl VoxelAtlas {
+    /// The amount of tiles in each row and column of the atlas, the `texture_atlas_dims`
+    /// argument of `generate_voxel_mesh`.
+    pub fn dims(&self) -> [u32; 2] {
+        self.dims
+    }
+
+    /// The `padding` argument of `generate_voxel_mesh` that matches the borders of the tiles.
+    pub fn padding(&self) -> f32 {
+        self.padding
+    }
+
+    /// The coordinates of a tile in the atlas, by name.
+    pub fn tile(&self, name: &str) -> Option<[u32; 2]> {
+        self.tiles.get(name).copied()
+    }
+
+    /// The `texture` argument of `generate_voxel_mesh`, from the name of the tile of each face.
+    /// None if one of the tiles isn't in the atlas.
+    pub fn faces(&self, faces: [(Face, &str); 6]) -> Option<[(Face, [u32; 2]); 6]> {
+        let mut r = [(Top, [0, 0]); 6];
+        for (i, (face, name)) in faces.into_iter().enumerate() {
+            r[i] = (face, self.tile(name)?);
+        }
+        Some(r)
+    }
+}
+
+// The pixels of a tile, 4 bytes each.
+fn rgba8_pixels(image: &Image, tile: usize) -> Result<Cow<'_, [u8]>, MeshemError> {
+    let format = image.texture_descriptor.format;
+    let image = match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Cow::Borrowed(image),
+        _ => Cow::Owned(
+            image
+                .convert(TextureFormat::Rgba8UnormSrgb)
+                .ok_or(MeshemError::UnsupportedTileFormat { tile, format })?,
+        ),
+    };
+    let expected = (image.width() * image.height() * 4) as usize;
+    match image {
+        Cow::Borrowed(image) => match image.data.as_deref() {
+            Some(data) if data.len() == expected => Ok(Cow::Borrowed(data)),
+            _ => Err(MeshemError::MissingTileData(tile)),
+        },
+        Cow::Owned(image) => match image.data {
+            Some(data) if data.len() == expected => Ok(Cow::Owned(data)),
+            _ => Err(MeshemError::MissingTileData(tile)),
+        },
+    }
+}
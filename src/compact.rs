@@ -0,0 +1,166 @@
+//! This module is responsible for keeping long-lived meshes in shape. Updating a mesh removes
+//! quads by swapping the last quad into their place, and appends the new quads at the end, so
+//! after a lot of updates the quads of neighboring voxels are scattered across the buffers.
+use crate::prelude::*;
+use bevy::mesh::Indices;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+/// Reorder the quads of the mesh by the index of their voxel (and by face), the same order
+/// `mesh_grid` generates them in, rewrite the metadata accordingly and shrink the buffers to
+/// their length (keeping the room of `VoxelRegistry::reserved_quads`). Vertices and triangles
+/// that don't belong to any quad in the metadata are dropped. The logged changes aren't
+/// affected, and can still be applied with `update_mesh`.
+/// Returns [`MeshemError::SplitMesh`] if the mesh was split by material, compact the sub-meshes
+/// with [`compact_sub_meshes`] instead.
+pub fn compact_mesh<T>(mesh: &mut Mesh, metadata: &mut MeshMD<T>) -> Result<(), MeshemError> {
+    if metadata.sub_meshes.is_some() {
+        return Err(MeshemError::SplitMesh);
+    }
+    compact(mesh, metadata)
+}
+
+/// Same as [`compact_mesh`], for meshes that were split by material (see
+/// `mesh_grid_by_material`). The sub-meshes are put back together, compacted, and split again,
+/// in the same order.
+pub fn compact_sub_meshes<T>(
+    sub_meshes: &mut SubMeshes,
+    metadata: &mut MeshMD<T>,
+) -> Result<(), MeshemError> {
+    let mut mesh = metadata.join_sub_meshes(sub_meshes)?;
+    compact(&mut mesh, metadata)?;
+    *sub_meshes = metadata.split_sub_meshes(&mesh)?;
+    Ok(())
+}
+
+fn compact<T>(mesh: &mut Mesh, metadata: &mut MeshMD<T>) -> Result<(), MeshemError> {
+    let vertices_count = mesh.count_vertices();
+
+    // The triangles of each quad (by its first vertex), in the order they appear in the mesh.
+    let mut triangles: HashMap<u32, Vec<[u32; 3]>> = HashMap::new();
+    for t in indices_of(mesh)?.chunks_exact(3) {
+        if t.iter().any(|v| v / 4 != t[0] / 4) {
+            continue;
+        }
+        triangles
+            .entry(t[0] / 4 * 4)
+            .or_default()
+            .push([t[0], t[1], t[2]]);
+    }
+
+    // The old index of every vertex, in the new order.
+    let mut order: Vec<u32> = Vec::with_capacity(vertices_count);
+    let mut indices: Vec<u32> =
+        Vec::with_capacity((vertices_count / 4 + metadata.reserved_quads) * 6);
+    let mut vivi = VIVI::new(metadata.vivi.vivi.len());
+    for (voxel_index, quads) in metadata.vivi.vivi.iter().enumerate() {
+        let mut quads: Vec<(Face, u32)> = quads
+            .iter()
+            .map(|q| (face_from_u32(q & REVERSE_OFFSET_CONST), q & OFFSET_CONST))
+            .collect();
+        quads.sort_unstable_by_key(|(face, _)| *face as usize);
+        for (face, old_vertex) in quads {
+            if old_vertex as usize + 4 > vertices_count {
+                return Err(MeshemError::VertexNotFound(old_vertex as usize));
+            }
+            let new_vertex = order.len() as u32;
+            order.extend(old_vertex..old_vertex + 4);
+            if metadata.vivi.is_interior(old_vertex) {
+                vivi.insert_interior(face, voxel_index, new_vertex);
+            } else {
+                vivi.insert(face, voxel_index, new_vertex);
+            }
+            for t in triangles.get(&old_vertex).into_iter().flatten() {
+                indices.extend(t.map(|v| v - old_vertex + new_vertex));
+            }
+        }
+    }
+
+    for (_, vals) in mesh.attributes_mut() {
+        *vals = vals.get_needed(&order);
+        vals.reserve(metadata.reserved_quads * 4);
+    }
+    mesh.insert_indices(Indices::U32(indices));
+    for quads in vivi.vivi.iter_mut() {
+        quads.shrink_to_fit();
+    }
+    vivi.map.shrink_to_fit();
+    metadata.vivi = vivi;
+    metadata.changed_voxels.shrink_to_fit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use bevy::mesh::VertexAttributeValues;
+
+    const DIMS: Dimensions = (4, 4, 4);
+
+    // Whether there is room for `quads` more quads in every buffer of the mesh.
+    fn has_room(mesh: &Mesh, quads: usize) -> bool {
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            return false;
+        };
+        let room =
+            |capacity: usize, len: usize, per_quad: usize| capacity - len >= quads * per_quad;
+        room(indices.capacity(), indices.len(), 6)
+            && mesh.attributes().all(|(_, vals)| match vals {
+                VertexAttributeValues::Float32x2(v) => room(v.capacity(), v.len(), 4),
+                VertexAttributeValues::Float32x3(v) => room(v.capacity(), v.len(), 4),
+                VertexAttributeValues::Float32x4(v) => room(v.capacity(), v.len(), 4),
+                _ => false,
+            })
+    }
+
+    #[test]
+    fn reserved_quads_are_kept() {
+        let mut reg = TestRegistry::new();
+        reg.reserved_quads = 50;
+        // Two layers of stone.
+        let mut grid = vec![0u16; 64];
+        grid[..32].fill(1);
+        let (mut mesh, mut md) =
+            try_mesh_grid(DIMS, &[], &grid, &reg, MeshingAlgorithm::Culling, None).unwrap();
+        assert!(has_room(&mesh, 50));
+
+        for index in 0..64 {
+            if grid[index] != 0 {
+                md.log(
+                    VoxelChange::Broken,
+                    index,
+                    grid[index],
+                    neighbors_of(&grid, DIMS, index),
+                );
+                grid[index] = 0;
+            }
+        }
+        update_mesh(&mut mesh, &mut md, &reg);
+        compact_mesh(&mut mesh, &mut md).unwrap();
+        assert_eq!(mesh.count_vertices(), 0);
+        assert!(has_room(&mesh, 50));
+    }
+
+    #[test]
+    fn split_meshes_are_compacted_by_sub_mesh() {
+        let reg = TestRegistry::new();
+        // A floor of stone, with a row of another material on it.
+        let mut grid = vec![0u16; 64];
+        grid[..16].fill(1);
+        grid[16..20].fill(4);
+        let (mut sub_meshes, mut md) =
+            mesh_grid_by_material(DIMS, &[], &grid, &reg, MeshingAlgorithm::Culling, None).unwrap();
+        let mut joined = md.join_sub_meshes(&sub_meshes).unwrap();
+        assert_eq!(
+            compact_mesh(&mut joined, &mut md),
+            Err(MeshemError::SplitMesh)
+        );
+
+        compact_sub_meshes(&mut sub_meshes, &mut md).unwrap();
+        let joined = md.join_sub_meshes(&sub_meshes).unwrap();
+        assert!(md
+            .verify(&joined, &FlatGrid::new(DIMS, &grid), &reg)
+            .is_ok());
+    }
+}
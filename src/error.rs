@@ -0,0 +1,215 @@
+//! This module contains [`MeshemError`], the error type returned by the fallible (`try_`) versions
+//! of the meshing, updating and Smooth Lighting functions.
+use crate::prelude::*;
+use bevy::mesh::{Indices, VertexAttributeValues};
+use bevy::prelude::*;
+use bevy::render::render_resource::{TextureFormat, VertexFormat};
+use std::fmt;
+
+/// Everything that could go wrong while meshing a grid or updating a mesh. Most of these are
+/// caused by a voxel mesh in the registry that doesn't fit the crate's expectations, or by
+/// metadata that doesn't belong to the mesh it's used with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshemError {
+    /// The length of the grid isn't the product of the dimensions.
+    GridSizeMismatch { expected: usize, found: usize },
+    /// A box (given by its minimum and maximum coordinates) isn't within the dimensions of the
+    /// grid.
+    RegionOutOfBounds {
+        min: [usize; 3],
+        max: [usize; 3],
+        dims: Dimensions,
+    },
+    /// A voxel index (for example, one that was logged) is outside of the grid.
+    VoxelIndexOutOfBounds { index: usize, len: usize },
+    /// A voxel was logged as added, but there is already a voxel there.
+    VoxelAlreadyExists(usize),
+    /// A voxel was logged as broken, but there is no voxel there.
+    VoxelDoesNotExist(usize),
+    /// A mesh is missing a vertex attribute that is needed (by name).
+    MissingAttribute(&'static str),
+    /// A vertex attribute of a mesh isn't in the format that is needed.
+    UnexpectedAttributeFormat {
+        attribute: &'static str,
+        expected: VertexFormat,
+        found: VertexFormat,
+    },
+    /// A mesh doesn't have the amount of vertices that is needed.
+    UnexpectedVertexCount { expected: usize, found: usize },
+    /// A value of the `ATTRIBUTE_FACE_TAG` of a voxel mesh isn't a face (with or without
+    /// `FACE_TAG_INTERIOR`).
+    InvalidFaceTag(u32),
+    /// A mesh doesn't have indices.
+    MissingIndices,
+    /// The indices of a mesh aren't U32.
+    UnexpectedIndicesFormat,
+    /// The metadata doesn't have a quad for this face of the voxel.
+    QuadNotFound { voxel_index: usize, face: Face },
+    /// The metadata doesn't have a voxel that owns this vertex, the metadata and the mesh are
+    /// out of sync.
+    VertexNotFound(usize),
+    /// An atlas was built without any tiles.
+    EmptyAtlas,
+    /// A tile of an atlas (by the order it was added) has no pixels.
+    EmptyTile(usize),
+    /// A tile of an atlas (by the order it was added) isn't the same size as the first tile.
+    TileSizeMismatch {
+        tile: usize,
+        expected: [u32; 2],
+        found: [u32; 2],
+    },
+    /// A tile of an atlas isn't RGBA8, and couldn't be converted to it.
+    UnsupportedTileFormat { tile: usize, format: TextureFormat },
+    /// The data of a tile of an atlas isn't available on the CPU.
+    MissingTileData(usize),
+    /// The texture animation of a face has no frames, its frames aren't evenly spaced in the
+    /// atlas, or its frame time isn't positive.
+    InvalidTextureAnimation(Face),
+    /// The sub-meshes (or the metadata) aren't the ones the mesh was split into with
+    /// `MeshMD::split_by_material`.
+    SubMeshMismatch,
+    /// The mesh was split by material (see `MeshMD::split_by_material`), and the function only
+    /// works with meshes that weren't.
+    SplitMesh,
+    /// The lock of the adjacent chunk in this direction is poisoned, a thread panicked while
+    /// writing to it.
+    PoisonedChunk(Direction),
+}
+
+impl fmt::Display for MeshemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GridSizeMismatch { expected, found } => write!(
+                f,
+                "The length of the grid ({}) must be the product of the dimensions ({})",
+                found, expected
+            ),
+            Self::RegionOutOfBounds { min, max, dims } => write!(
+                f,
+                "The box from {:?} to {:?} isn't within the dimensions of the grid {:?}",
+                min, max, dims
+            ),
+            Self::VoxelIndexOutOfBounds { index, len } => write!(
+                f,
+                "Voxel index {} is out of the bounds of the grid (length {})",
+                index, len
+            ),
+            Self::VoxelAlreadyExists(index) => {
+                write!(f, "Can't add voxel {}, it already exists", index)
+            }
+            Self::VoxelDoesNotExist(index) => {
+                write!(f, "Can't break voxel {}, it doesn't exist", index)
+            }
+            Self::MissingAttribute(name) => write!(f, "Couldn't get mesh attribute {}", name),
+            Self::UnexpectedAttributeFormat {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Unexpected format for attribute {}, expected {:?}, found {:?}",
+                attribute, expected, found
+            ),
+            Self::UnexpectedVertexCount { expected, found } => write!(
+                f,
+                "Expected a mesh with {} vertices, found {}",
+                expected, found
+            ),
+            Self::InvalidFaceTag(tag) => write!(f, "{} isn't a valid face tag", tag),
+            Self::MissingIndices => write!(f, "Couldn't get indices data"),
+            Self::UnexpectedIndicesFormat => write!(f, "Expected U32 indices format"),
+            Self::QuadNotFound { voxel_index, face } => write!(
+                f,
+                "Couldn't find the {:?} quad of voxel {} in the metadata",
+                face, voxel_index
+            ),
+            Self::VertexNotFound(vertex) => {
+                write!(f, "Couldn't find voxel matching vertex {}", vertex)
+            }
+            Self::EmptyAtlas => write!(f, "Can't build an atlas without tiles"),
+            Self::EmptyTile(tile) => write!(f, "Tile {} has no pixels", tile),
+            Self::TileSizeMismatch {
+                tile,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Tile {} is {:?} pixels, all the tiles must be {:?} pixels",
+                tile, found, expected
+            ),
+            Self::UnsupportedTileFormat { tile, format } => write!(
+                f,
+                "Tile {} is {:?}, and couldn't be converted to RGBA8",
+                tile, format
+            ),
+            Self::MissingTileData(tile) => {
+                write!(f, "The data of tile {} isn't available on the CPU", tile)
+            }
+            Self::InvalidTextureAnimation(face) => write!(
+                f,
+                "The texture animation of the {:?} face needs evenly spaced frames and a positive frame time",
+                face
+            ),
+            Self::SubMeshMismatch => write!(
+                f,
+                "The sub-meshes don't match the way the metadata split the mesh"
+            ),
+            Self::SplitMesh => write!(
+                f,
+                "The mesh was split by material, this needs a mesh that wasn't split"
+            ),
+            Self::PoisonedChunk(direction) => write!(
+                f,
+                "The lock of the adjacent chunk in {:?} is poisoned",
+                direction
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshemError {}
+
+impl MeshemError {
+    pub(crate) fn check_grid_size(dims: Dimensions, len: usize) -> Result<(), MeshemError> {
+        let expected = dims.0 * dims.1 * dims.2;
+        if len != expected {
+            return Err(MeshemError::GridSizeMismatch {
+                expected,
+                found: len,
+            });
+        }
+        Ok(())
+    }
+}
+
+// The positions of a mesh, they must be Float32x3.
+pub(crate) fn positions_of(mesh: &Mesh) -> Result<&Vec<[f32; 3]>, MeshemError> {
+    let positions = mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .ok_or(MeshemError::MissingAttribute(Mesh::ATTRIBUTE_POSITION.name))?;
+    let VertexAttributeValues::Float32x3(positions) = positions else {
+        return Err(MeshemError::UnexpectedAttributeFormat {
+            attribute: Mesh::ATTRIBUTE_POSITION.name,
+            expected: VertexFormat::Float32x3,
+            found: positions.into(),
+        });
+    };
+    Ok(positions)
+}
+
+// The indices of a mesh, they must be U32.
+pub(crate) fn indices_of(mesh: &Mesh) -> Result<&Vec<u32>, MeshemError> {
+    match mesh.indices() {
+        None => Err(MeshemError::MissingIndices),
+        Some(Indices::U32(indices)) => Ok(indices),
+        Some(_) => Err(MeshemError::UnexpectedIndicesFormat),
+    }
+}
+
+pub(crate) fn indices_of_mut(mesh: &mut Mesh) -> Result<&mut Vec<u32>, MeshemError> {
+    match mesh.indices_mut() {
+        None => Err(MeshemError::MissingIndices),
+        Some(Indices::U32(indices)) => Ok(indices),
+        Some(_) => Err(MeshemError::UnexpectedIndicesFormat),
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// This enum represents all the faces of a cubic voxel.
 pub enum Face {
     Top,
@@ -60,6 +62,18 @@ impl Face {
             Face::Forward => Face::Back,
         }
     }
+
+    /// The direction the `Face` is facing, as a change in (x, y, z) coordinates.
+    pub fn normal(&self) -> [i32; 3] {
+        match *self {
+            Face::Top => [0, 1, 0],
+            Face::Bottom => [0, -1, 0],
+            Face::Right => [1, 0, 0],
+            Face::Left => [-1, 0, 0],
+            Face::Back => [0, 0, 1],
+            Face::Forward => [0, 0, -1],
+        }
+    }
 }
 
 impl Into<usize> for Face {
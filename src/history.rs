@@ -0,0 +1,382 @@
+//! This module contains the opt-in edit history of a mesh. When it's enabled, every batch of
+//! changes applied with `update_mesh` is saved, and can be undone (and redone) by logging the
+//! inverse batch and updating the mesh again, through the same incremental path.
+use crate::prelude::*;
+use bevy::log::warn;
+use std::collections::VecDeque;
+
+// A logged change, same as in the change log of `MeshMD`.
+pub(crate) type LoggedChange<T> = (T, usize, VoxelChange<T>, [Option<T>; 6]);
+
+/// A voxel that changed because of an undo or a redo. The grid the mesh was generated from
+/// needs to be updated with these, in order, before the next changes are logged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelEdit<T> {
+    /// The index of the voxel in the grid.
+    pub index: usize,
+    /// The voxel before the edit, None if it was empty.
+    pub old: Option<T>,
+    /// The voxel after the edit, None if it's empty.
+    pub new: Option<T>,
+}
+
+// Where the batch that will be applied next came from.
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    Edit,
+    Undo,
+    Redo,
+}
+
+pub(crate) struct EditHistory<T> {
+    undo: VecDeque<Vec<LoggedChange<T>>>,
+    redo: Vec<Vec<LoggedChange<T>>>,
+    // The maximum amount of batches that can be undone.
+    limit: usize,
+    pending: Pending,
+}
+
+impl<T> EditHistory<T> {
+    fn new(limit: usize) -> Self {
+        EditHistory {
+            undo: VecDeque::new(),
+            redo: vec![],
+            limit,
+            pending: Pending::Edit,
+        }
+    }
+
+    // Save a batch of changes that was just applied to the mesh.
+    fn record(&mut self, batch: Vec<LoggedChange<T>>) {
+        let pending = std::mem::replace(&mut self.pending, Pending::Edit);
+        // Only actual edits are saved, culling against adjacent chunks isn't undoable.
+        let batch: Vec<LoggedChange<T>> = batch
+            .into_iter()
+            .filter(|(_, _, change, _)| {
+                !matches!(change, VoxelChange::CullFaces | VoxelChange::AddFaces)
+            })
+            .collect();
+        if batch.is_empty() {
+            return;
+        }
+        match pending {
+            Pending::Undo => self.redo.push(batch),
+            Pending::Redo => self.push_undo(batch),
+            Pending::Edit => {
+                self.redo.clear();
+                self.push_undo(batch);
+            }
+        }
+    }
+
+    fn push_undo(&mut self, batch: Vec<LoggedChange<T>>) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.undo.len() == self.limit {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(batch);
+    }
+}
+
+impl<T> MeshMD<T> {
+    /// Start saving the batches of changes applied with `update_mesh`, so they can be undone
+    /// with [`MeshMD::undo`]. Up to `limit` batches are kept, the oldest ones are dropped first.
+    /// If the history is already enabled, only the limit is changed.
+    pub fn enable_history(&mut self, limit: usize) {
+        match self.history.as_mut() {
+            Some(history) => {
+                history.limit = limit;
+                while history.undo.len() > limit {
+                    history.undo.pop_front();
+                }
+            }
+            None => self.history = Some(EditHistory::new(limit)),
+        }
+    }
+
+    /// Stop saving changes, and drop the saved history.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Drop the saved history, without disabling it.
+    pub fn clear_history(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.undo.clear();
+            history.redo.clear();
+        }
+    }
+
+    /// Whether there is a batch of changes that can be undone.
+    pub fn can_undo(&self) -> bool {
+        self.history.as_ref().is_some_and(|h| !h.undo.is_empty())
+    }
+
+    /// Whether there is a batch of changes that can be redone.
+    pub fn can_redo(&self) -> bool {
+        self.history.as_ref().is_some_and(|h| !h.redo.is_empty())
+    }
+
+    // Called by `update_mesh` with the batch of changes it applied.
+    pub(crate) fn record_history(&mut self, batch: Vec<LoggedChange<T>>) {
+        if let Some(history) = self.history.as_mut() {
+            history.record(batch);
+        }
+    }
+}
+
+impl<T: Copy> MeshMD<T> {
+    /// Log the inverse of the last batch of changes that was applied with `update_mesh`. The mesh
+    /// is changed back by calling `update_mesh` afterwards, and the returned edits need to be
+    /// applied to the grid.
+    /// Returns None if the history isn't enabled, if there is nothing to undo, or if there are
+    /// logged changes that weren't applied yet.
+    pub fn undo(&mut self) -> Option<Vec<VoxelEdit<T>>> {
+        if !self.changed_voxels.is_empty() {
+            return None;
+        }
+        let batch = self.history.as_mut()?.undo.pop_back()?;
+        self.history.as_mut().unwrap().pending = Pending::Undo;
+        Some(self.log_inverse(batch))
+    }
+
+    /// Log the last batch of changes that was undone again, the same way as [`MeshMD::undo`].
+    /// Logging (and applying) new changes drops the batches that can be redone.
+    pub fn redo(&mut self) -> Option<Vec<VoxelEdit<T>>> {
+        if !self.changed_voxels.is_empty() {
+            return None;
+        }
+        let batch = self.history.as_mut()?.redo.pop()?;
+        self.history.as_mut().unwrap().pending = Pending::Redo;
+        Some(self.log_inverse(batch))
+    }
+
+    // Log the inverse of every change in the batch, from the last to the first. The neighbors
+    // of each change are the ones it was logged with, because undoing the changes after it
+    // restores them.
+    fn log_inverse(&mut self, batch: Vec<LoggedChange<T>>) -> Vec<VoxelEdit<T>> {
+        let mut edits = Vec::with_capacity(batch.len());
+        for (voxel, index, change, neighbors) in batch.into_iter().rev() {
+            let (inverse, inverse_voxel, edit) = match change {
+                VoxelChange::Added => (
+                    VoxelChange::Broken,
+                    voxel,
+                    VoxelEdit {
+                        index,
+                        old: Some(voxel),
+                        new: None,
+                    },
+                ),
+                VoxelChange::Broken => (
+                    VoxelChange::Added,
+                    voxel,
+                    VoxelEdit {
+                        index,
+                        old: None,
+                        new: Some(voxel),
+                    },
+                ),
+                VoxelChange::Replaced { old } => (
+                    VoxelChange::Replaced { old: voxel },
+                    old,
+                    VoxelEdit {
+                        index,
+                        old: Some(voxel),
+                        new: Some(old),
+                    },
+                ),
+                // Only added, broken and replaced voxels are saved.
+                VoxelChange::CullFaces | VoxelChange::AddFaces => continue,
+            };
+            if let Err(e) = self.try_log(inverse, index, inverse_voxel, neighbors) {
+                warn!("Couldn't undo the change to voxel {}: {}", index, e);
+                continue;
+            }
+            edits.push(edit);
+        }
+        edits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::prelude::*;
+
+    const DIMS: Dimensions = (4, 4, 4);
+
+    fn mesh(grid: &[u16]) -> (Mesh, MeshMD<u16>) {
+        try_mesh_grid(
+            DIMS,
+            &[],
+            grid,
+            &TestRegistry::new(),
+            MeshingAlgorithm::Culling,
+            None,
+        )
+        .unwrap()
+    }
+
+    // Log a change to the voxel at `index` and make it in the grid.
+    fn edit(md: &mut MeshMD<u16>, grid: &mut [u16], index: usize, voxel: u16) {
+        let old = grid[index];
+        let neighbors = neighbors_of(grid, DIMS, index);
+        let change = match (old, voxel) {
+            (0, v) => (VoxelChange::Added, v),
+            (o, 0) => (VoxelChange::Broken, o),
+            (o, v) => (VoxelChange::Replaced { old: o }, v),
+        };
+        md.log(change.0, index, change.1, neighbors);
+        grid[index] = voxel;
+    }
+
+    // Apply the edits returned by undo or redo to the grid, and update the mesh.
+    fn apply(edits: Vec<VoxelEdit<u16>>, grid: &mut [u16], mesh: &mut Mesh, md: &mut MeshMD<u16>) {
+        for e in edits {
+            assert_eq!(grid[e.index], e.old.unwrap_or(0));
+            grid[e.index] = e.new.unwrap_or(0);
+        }
+        update_mesh(mesh, md, &TestRegistry::new());
+    }
+
+    // Check the mesh against a fresh mesh of the grid.
+    fn assert_matches(mesh: &Mesh, md: &MeshMD<u16>, grid: &[u16]) {
+        let report = md.verify(mesh, &FlatGrid::new(DIMS, grid), &TestRegistry::new());
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn undo_then_redo_restores_the_mesh() {
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut grid = random_grid(&mut rng, 64, 3);
+            let (mut mesh, mut md) = mesh(&grid);
+            md.enable_history(8);
+            let reg = TestRegistry::new();
+            let grid_before = grid.clone();
+
+            for _ in 0..10 {
+                let index = rng.gen_range(0..64);
+                let voxel = match grid[index] {
+                    0 => rng.gen_range(1..=3),
+                    // Break it, or replace it with a different one.
+                    v => [0, v % 3 + 1][rng.gen_range(0..2)],
+                };
+                edit(&mut md, &mut grid, index, voxel);
+            }
+            update_mesh(&mut mesh, &mut md, &reg);
+            let grid_after = grid.clone();
+            assert!(md.can_undo());
+            assert!(!md.can_redo());
+
+            let edits = md.undo().unwrap();
+            apply(edits, &mut grid, &mut mesh, &mut md);
+            assert_eq!(grid, grid_before);
+            assert_matches(&mesh, &md, &grid);
+            assert!(!md.can_undo());
+            assert!(md.can_redo());
+
+            let edits = md.redo().unwrap();
+            apply(edits, &mut grid, &mut mesh, &mut md);
+            assert_eq!(grid, grid_after);
+            assert_matches(&mesh, &md, &grid);
+            assert!(md.can_undo());
+            assert!(!md.can_redo());
+        }
+    }
+
+    #[test]
+    fn replaced_voxels_are_inverted() {
+        let mut grid = vec![0u16; 64];
+        grid[0..16].fill(1);
+        let (mut mesh, mut md) = mesh(&grid);
+        md.enable_history(8);
+
+        edit(&mut md, &mut grid, 5, 2);
+        edit(&mut md, &mut grid, 6, 3);
+        update_mesh(&mut mesh, &mut md, &TestRegistry::new());
+        assert_matches(&mesh, &md, &grid);
+
+        let edits = md.undo().unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                VoxelEdit {
+                    index: 6,
+                    old: Some(3),
+                    new: Some(1)
+                },
+                VoxelEdit {
+                    index: 5,
+                    old: Some(2),
+                    new: Some(1)
+                },
+            ]
+        );
+        apply(edits, &mut grid, &mut mesh, &mut md);
+        assert_eq!(grid[5..7], [1, 1]);
+        assert_matches(&mesh, &md, &grid);
+
+        let edits = md.redo().unwrap();
+        assert_eq!(edits[0].new, Some(2));
+        assert_eq!(edits[1].new, Some(3));
+        apply(edits, &mut grid, &mut mesh, &mut md);
+        assert_eq!(grid[5..7], [2, 3]);
+        assert_matches(&mesh, &md, &grid);
+    }
+
+    #[test]
+    fn the_oldest_batches_are_dropped() {
+        let mut grid = vec![0u16; 64];
+        let (mut mesh, mut md) = mesh(&grid);
+        let reg = TestRegistry::new();
+        md.enable_history(2);
+        for index in 0..3 {
+            edit(&mut md, &mut grid, index, 1);
+            update_mesh(&mut mesh, &mut md, &reg);
+        }
+
+        for index in [2, 1] {
+            let edits = md.undo().unwrap();
+            assert_eq!(edits.len(), 1);
+            assert_eq!(edits[0].index, index);
+            apply(edits, &mut grid, &mut mesh, &mut md);
+        }
+        // The first batch was dropped.
+        assert!(!md.can_undo());
+        assert_eq!(md.undo(), None);
+        assert_eq!(grid[0], 1);
+
+        // Lowering the limit drops batches too.
+        for _ in 0..2 {
+            let edits = md.redo().unwrap();
+            apply(edits, &mut grid, &mut mesh, &mut md);
+        }
+        md.enable_history(1);
+        assert_eq!(md.undo().unwrap()[0].index, 2);
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut grid = vec![0u16; 64];
+        let (mut mesh, mut md) = mesh(&grid);
+        let reg = TestRegistry::new();
+        md.enable_history(8);
+        edit(&mut md, &mut grid, 0, 1);
+        update_mesh(&mut mesh, &mut md, &reg);
+        let edits = md.undo().unwrap();
+        apply(edits, &mut grid, &mut mesh, &mut md);
+        assert!(md.can_redo());
+
+        edit(&mut md, &mut grid, 1, 1);
+        // Can't undo or redo while there are changes that weren't applied.
+        assert_eq!(md.undo(), None);
+        update_mesh(&mut mesh, &mut md, &reg);
+        assert!(!md.can_redo());
+        assert_eq!(md.redo(), None);
+        assert_eq!(md.undo().unwrap()[0].index, 1);
+    }
+}
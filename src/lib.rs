@@ -1,10 +1,24 @@
 pub(crate) mod adj;
+pub(crate) mod animation;
+pub(crate) mod atlas;
+pub(crate) mod compact;
+pub(crate) mod error;
 pub(crate) mod face;
+pub(crate) mod history;
 pub(crate) mod mesh_metadata;
 pub(crate) mod meshem;
+pub(crate) mod orientation;
 pub(crate) mod pbs;
+pub(crate) mod region;
+pub(crate) mod registry_asset;
+pub(crate) mod sub_mesh;
+pub(crate) mod texture_array;
+#[cfg(test)]
+pub(crate) mod test_utils;
 pub(crate) mod update;
 pub mod util;
+pub(crate) mod verify;
+pub(crate) mod voxel_grid;
 pub(crate) mod voxel_mesh;
 
 use bevy::log::warn;
@@ -13,15 +27,28 @@ use bevy::prelude::*;
 
 pub mod prelude {
     pub use crate::adj::*;
+    pub use crate::animation::*;
+    pub use crate::atlas::*;
+    pub use crate::compact::*;
+    pub use crate::error::*;
     pub use crate::face::Face::*;
     pub use crate::face::*;
+    pub use crate::history::*;
     pub use crate::mesh_metadata::*;
     pub use crate::meshem::*;
+    pub use crate::orientation::*;
     pub use crate::pbs::*;
+    pub use crate::region::*;
+    pub use crate::registry_asset::*;
+    pub use crate::sub_mesh::*;
+    pub use crate::texture_array::*;
     pub use crate::update::*;
-    pub(crate) use crate::util::compressed_voxel_grid::*;
+    pub use crate::util::compressed_voxel_grid::*;
+    pub use crate::util::octree::*;
     pub use crate::util::vav::*;
     pub use crate::util::*;
+    pub use crate::verify::*;
+    pub use crate::voxel_grid::*;
     pub use crate::voxel_mesh::*;
     pub use crate::VoxelRegistry;
     pub use crate::*;
@@ -42,6 +69,41 @@ pub trait VoxelRegistry {
     fn get_voxel_dimensions(&self) -> [f32; 3];
     /// The attributes we are considering while meshing the grid.
     fn all_attributes(&self) -> Vec<MeshVertexAttribute>;
+    /// Should the textures of this voxel be rotated randomly (by 0, 90, 180 or 270 degrees) on
+    /// each of its faces, to break up the repetition of textures like grass and stone. The
+    /// rotation is a hash of the position of the voxel in the grid and of the face, so meshing
+    /// (or updating) the grid always gives the same result. Only applies to voxels that aren't
+    /// shaped, and expects square tiles. False by default.
+    fn randomize_uv_rotation(&self, _voxel: &Self::Voxel) -> bool {
+        false
+    }
+    /// The orientation of this voxel (see [`prelude::Orientation`]), its mesh is rotated by it
+    /// before it's meshed, and `is_covering` is asked about the side of the voxel before it was
+    /// rotated, so a directional voxel only needs one mesh. Rotations that swap axes expect the
+    /// voxel dimensions to be equal on those axes. No rotation by default.
+    fn get_orientation(&self, _voxel: &Self::Voxel) -> prelude::Orientation {
+        prelude::Orientation::IDENTITY
+    }
+    /// A color the vertex colors of the voxel at `cords` (its position in the grid that is
+    /// meshed) are multiplied by, for example to color grass and leaves by their biome. Smooth
+    /// Lighting is multiplied by it too, instead of replacing it. Only applies if the colors are
+    /// in `all_attributes`. None (no tint) by default.
+    fn get_tint(&self, _voxel: &Self::Voxel, _cords: [usize; 3]) -> Option<[f32; 4]> {
+        None
+    }
+    /// The key of the material this voxel is drawn with (for example 0 for stone, 1 for metal,
+    /// 2 for emissive crystals), [`prelude::mesh_grid_by_material`] puts the quads of each key
+    /// in a mesh of their own. 0 by default.
+    fn get_material_key(&self, _voxel: &Self::Voxel) -> u32 {
+        0
+    }
+    /// How many quads to keep room for in the buffers of the meshes generated with this
+    /// registry, on top of the quads they were generated with, so the updates that add quads
+    /// don't need to reallocate them. The room is reserved when the buffers are built, and again
+    /// when `compact_mesh` rebuilds them. 0 by default.
+    fn reserved_quads(&self) -> usize {
+        0
+    }
 }
 
 /// (width, height, length) - note that bevy considers the "y position" to be height.
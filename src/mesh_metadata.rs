@@ -1,13 +1,18 @@
 use crate::prelude::*;
-use bevy::platform::collections::HashMap;
+use bevy::log::warn;
+use bevy::platform::collections::{HashMap, HashSet};
 
 // Data structure "Voxel index to Vertex index", it is used in the meta-data to save which
 // vertex belongs to which voxel. The `vivi` field is the same length as the length of the grid,
 // each element of the field is a vector of its quads (a quad is 2 triangles (indices) made out of
 // 4 vertices to create a square, which )
+// Quads of shaped voxels that aren't on the side of the voxel they face (see
+// `ATTRIBUTE_FACE_TAG`) are interior quads, they are never culled, and their first vertices are
+// kept in `interior`.
 pub(crate) struct VIVI {
     pub(crate) vivi: Vec<Vec<u32>>,
     pub(crate) map: HashMap<u32, u32>,
+    pub(crate) interior: HashSet<u32>,
 }
 
 impl VIVI {
@@ -15,6 +20,7 @@ impl VIVI {
         VIVI {
             vivi: vec![vec![]; voxel_count],
             map: HashMap::new(),
+            interior: HashSet::new(),
         }
     }
 
@@ -24,61 +30,96 @@ impl VIVI {
             .insert(vertex, voxel_index as u32 | face_to_u32(face));
     }
 
+    pub(crate) fn insert_interior(&mut self, face: Face, voxel_index: usize, vertex: u32) {
+        self.insert(face, voxel_index, vertex);
+        self.interior.insert(vertex);
+    }
+
+    // The first quad of the voxel on its `face` side, interior quads facing `face` don't count.
     pub(crate) fn get_quad_index(&self, face: Face, voxel_index: usize) -> Option<u32> {
         for quad in self.vivi[voxel_index].iter() {
             let tmp = quad & !OFFSET_CONST;
-            if tmp == face_to_u32(face) {
+            if tmp == face_to_u32(face) && !self.interior.contains(&(quad & OFFSET_CONST)) {
                 return Some(quad & (OFFSET_CONST));
             }
         }
         None
     }
 
-    pub(crate) fn change_quad_index(&mut self, old_vertex: usize, new_vertex: usize) {
+    pub(crate) fn get_interior_quad_index(&self, voxel_index: usize) -> Option<u32> {
+        self.vivi[voxel_index]
+            .iter()
+            .map(|quad| quad & OFFSET_CONST)
+            .find(|vertex| self.interior.contains(vertex))
+    }
+
+    pub(crate) fn is_interior(&self, vertex: u32) -> bool {
+        self.interior.contains(&vertex)
+    }
+
+    pub(crate) fn change_quad_index(
+        &mut self,
+        old_vertex: usize,
+        new_vertex: usize,
+    ) -> Result<(), MeshemError> {
         let voxel = self
             .map
             .remove(&(old_vertex as u32))
-            .expect(format!("Couldn't find voxel matching vertex {}", old_vertex).as_str());
+            .ok_or(MeshemError::VertexNotFound(old_vertex))?;
         let q = voxel & !OFFSET_CONST;
         let v = voxel & OFFSET_CONST;
-        let old_vertex = old_vertex as u32 | q;
+        let old_vertex_encoded = old_vertex as u32 | q;
         for v in self.vivi[v as usize].iter_mut() {
-            if *v == old_vertex {
+            if *v == old_vertex_encoded {
                 *v = new_vertex as u32 | q;
                 self.map.insert(new_vertex as u32, voxel);
-                return;
+                if self.interior.remove(&(old_vertex as u32)) {
+                    self.interior.insert(new_vertex as u32);
+                }
+                return Ok(());
             }
         }
-        panic!("Couldn't find vertex index in VIVI");
+        Err(MeshemError::VertexNotFound(old_vertex))
     }
 
-    pub(crate) fn remove_quad(&mut self, old_vertex: usize) {
+    pub(crate) fn remove_quad(&mut self, old_vertex: usize) -> Result<(), MeshemError> {
         let voxel = self
             .map
             .remove(&(old_vertex as u32))
-            .expect("Couldn't find voxel matching vertex");
+            .ok_or(MeshemError::VertexNotFound(old_vertex))?;
         let q = voxel & !OFFSET_CONST;
         let v = voxel & OFFSET_CONST;
-        let old_vertex = old_vertex as u32 | q;
+        let old_vertex_encoded = old_vertex as u32 | q;
         let mut r = (false, 0);
         for (i, j) in self.vivi[v as usize].iter().enumerate() {
-            if *j == old_vertex {
+            if *j == old_vertex_encoded {
                 r = (true, i);
             }
         }
         if r.0 {
             self.vivi[v as usize].swap_remove(r.1);
+            self.interior.remove(&(old_vertex as u32));
+            Ok(())
         } else {
-            panic!("Couldn't find quad from vertex");
+            Err(MeshemError::VertexNotFound(old_vertex))
         }
     }
 }
 
 /// This enum represents all the way a voxel could be changed.
+/// T is the voxel type, used by [`VoxelChange::Replaced`]. Before it was added the enum wasn't
+/// generic, code that names the type needs to write `VoxelChange<T>` instead of `VoxelChange`.
 #[derive(Clone, Copy)]
-pub enum VoxelChange {
+pub enum VoxelChange<T> {
     Broken,
     Added,
+    /// The voxel was replaced by another (non-empty) voxel, `old` is the voxel that was there
+    /// before. If both voxels are normal cubes that cover the same sides, only the attributes of
+    /// the existing quads are rewritten, otherwise it's the same as breaking the old voxel and
+    /// adding the new one.
+    Replaced {
+        old: T,
+    },
     CullFaces,
     AddFaces,
 }
@@ -88,36 +129,243 @@ pub enum VoxelChange {
 pub struct MeshMD<T> {
     pub(crate) vivi: VIVI,
     pub(crate) smooth_lighting_params: Option<SmoothLightingParameters>,
+    // The arguments the mesh was generated with.
+    pub(crate) outer_layer: Vec<Face>,
+    pub(crate) meshing_algorithm: MeshingAlgorithm,
+    // The room for more quads that is kept when the buffers are built (see
+    // `VoxelRegistry::reserved_quads`).
+    pub(crate) reserved_quads: usize,
     /// The dimensions of the 3d grid.
     pub dims: Dimensions,
+    // Whether each voxel of the grid exists (isn't Null), as of the last logged change.
+    pub(crate) occupancy: Rle<bool>,
     // T: the voxel type,
     // u32: the index of the voxel in the grid,
-    // ChangeInVoxel: whether the voxel was added or removed,
+    // ChangeInVoxel: whether the voxel was added, removed or replaced,
     // [Option<T>; 6]: the neighbors of the voxel, in the same order as
     //    in the `Neighbors` data-type, if the voxel is "empty"- None.
-    pub(crate) changed_voxels: Vec<(T, usize, VoxelChange, [Option<T>; 6])>,
+    pub(crate) changed_voxels: Vec<LoggedChange<T>>,
+    // The applied changes that can be undone, None unless it was enabled.
+    pub(crate) history: Option<EditHistory<T>>,
+    // Where the quads are in the sub-meshes, None unless the mesh was split by material.
+    pub(crate) sub_meshes: Option<SubMeshLayout>,
 }
 
 impl<T> MeshMD<T> {
+    /// Get read only of the `SmoothLightingParameters`
+    pub fn get_sl_params(&self) -> Option<SmoothLightingParameters> {
+        self.smooth_lighting_params
+    }
+
+    /// The edges of the grid that were culled when the mesh was generated.
+    pub fn get_outer_layer(&self) -> &[Face] {
+        &self.outer_layer
+    }
+
+    /// The meshing algorithm the mesh was generated with.
+    pub fn get_meshing_algorithm(&self) -> MeshingAlgorithm {
+        self.meshing_algorithm
+    }
+
+    /// Whether the voxel at `voxel_index` exists (isn't Null), taking the logged changes into
+    /// account. None if the index is out of the bounds of the grid.
+    pub fn is_occupied(&self, voxel_index: usize) -> Option<bool> {
+        self.occupancy.get(voxel_index)
+    }
+}
+
+impl<T: Copy> MeshMD<T> {
     /// Log the changes to the voxels.
-    /// `voxel_change`: [`VoxelChange`], Added or broken.
+    /// `voxel_change`: [`VoxelChange`], Added, broken or replaced.
     /// `voxel_index`: the index of the voxel in the 1-dimensional grid.
-    /// `voxel`: The voxel itself, same type as in the voxel registry.
+    /// `voxel`: The voxel itself, same type as in the voxel registry (the new voxel, if it was
+    ///     replaced).
     /// `neighboring_voxels`: Array where each element is the voxel in that direction.
     ///     (see Face from usize to understand which index represents which direction)
-    /// Adding a voxel that already exists, or breaking one that doesn't is undefined behaviour.
+    /// Adding a voxel that already exists, or breaking one that doesn't, is ignored with a
+    /// warning, see [`MeshMD::try_log`].
     pub fn log(
         &mut self,
-        voxel_change: VoxelChange,
+        voxel_change: VoxelChange<T>,
         voxel_index: usize,
         voxel: T,
         neighboring_voxels: [Option<T>; 6],
     ) {
+        if let Err(e) = self.try_log(voxel_change, voxel_index, voxel, neighboring_voxels) {
+            warn!("Ignoring change to voxel {}: {}", voxel_index, e);
+        }
+    }
+
+    /// Same as [`MeshMD::log`], but returns an error if the change is impossible (adding a voxel
+    /// that already exists, breaking one that doesn't, or a voxel outside of the grid), in which
+    /// case nothing is logged.
+    /// Breaking a voxel that was added since the last update cancels the two changes out, as long
+    /// as none of its neighbors were changed in between.
+    pub fn try_log(
+        &mut self,
+        voxel_change: VoxelChange<T>,
+        voxel_index: usize,
+        voxel: T,
+        neighboring_voxels: [Option<T>; 6],
+    ) -> Result<(), MeshemError> {
+        let Some(occupied) = self.occupancy.get(voxel_index) else {
+            return Err(MeshemError::VoxelIndexOutOfBounds {
+                index: voxel_index,
+                len: self.occupancy.len(),
+            });
+        };
+        match voxel_change {
+            VoxelChange::Added if occupied => {
+                return Err(MeshemError::VoxelAlreadyExists(voxel_index))
+            }
+            VoxelChange::Broken | VoxelChange::Replaced { .. } if !occupied => {
+                return Err(MeshemError::VoxelDoesNotExist(voxel_index))
+            }
+            VoxelChange::Added => {
+                self.occupancy.set(voxel_index, true);
+            }
+            VoxelChange::Broken => {
+                self.occupancy.set(voxel_index, false);
+                if self.cancel_pending_add(voxel_index) {
+                    return Ok(());
+                }
+            }
+            VoxelChange::Replaced { .. } | VoxelChange::CullFaces | VoxelChange::AddFaces => {}
+        }
         self.changed_voxels
             .push((voxel, voxel_index, voxel_change, neighboring_voxels));
+        Ok(())
     }
-    /// Get read only of the `SmoothLightingParameters`
-    pub fn get_sl_params(&self) -> Option<SmoothLightingParameters> {
-        self.smooth_lighting_params
+
+    // If the last logged change to the voxel is it being added, and none of the changes after it
+    // involve its neighbors (which would have seen it), remove it and return true.
+    fn cancel_pending_add(&mut self, voxel_index: usize) -> bool {
+        let Some(added) = self
+            .changed_voxels
+            .iter()
+            .rposition(|(_, index, _, _)| *index == voxel_index)
+        else {
+            return false;
+        };
+        if !matches!(self.changed_voxels[added].2, VoxelChange::Added) {
+            return false;
+        }
+        let neighbors: Vec<usize> = (0..6)
+            .filter_map(|f| get_neighbor(voxel_index, Face::from(f), self.dims))
+            .collect();
+        if self.changed_voxels[added + 1..]
+            .iter()
+            .any(|(_, index, _, _)| neighbors.contains(index))
+        {
+            return false;
+        }
+        self.changed_voxels.remove(added);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const DIMS: Dimensions = (3, 3, 3);
+
+    // The metadata of a grid that only has a voxel in its corner.
+    fn metadata() -> MeshMD<u16> {
+        let mut grid = vec![0u16; 27];
+        grid[0] = 1;
+        let (_, metadata) = try_mesh_grid(
+            DIMS,
+            &[],
+            &grid,
+            &TestRegistry::new(),
+            MeshingAlgorithm::Culling,
+            None,
+        )
+        .unwrap();
+        metadata
+    }
+
+    #[test]
+    fn breaking_an_added_voxel_cancels_out() {
+        let mut md = metadata();
+        let grid = vec![0u16; 27];
+        md.try_log(VoxelChange::Added, 13, 2, neighbors_of(&grid, DIMS, 13))
+            .unwrap();
+        assert_eq!(md.changed_voxels.len(), 1);
+        assert_eq!(md.is_occupied(13), Some(true));
+        md.try_log(VoxelChange::Broken, 13, 2, neighbors_of(&grid, DIMS, 13))
+            .unwrap();
+        assert!(md.changed_voxels.is_empty());
+        assert_eq!(md.is_occupied(13), Some(false));
+
+        // Changes to other voxels in between don't matter, as long as they aren't neighbors.
+        md.try_log(VoxelChange::Added, 13, 2, [None; 6]).unwrap();
+        md.try_log(VoxelChange::Added, 26, 2, [None; 6]).unwrap();
+        md.try_log(VoxelChange::Broken, 13, 2, [None; 6]).unwrap();
+        assert_eq!(md.changed_voxels.len(), 1);
+        assert_eq!(md.changed_voxels[0].1, 26);
+    }
+
+    #[test]
+    fn breaking_an_added_voxel_after_its_neighbor_changed_is_logged() {
+        let mut md = metadata();
+        md.try_log(VoxelChange::Added, 13, 2, [None; 6]).unwrap();
+        // 14 is the neighbor of 13 on its right, it was added after 13, and saw it.
+        md.try_log(
+            VoxelChange::Added,
+            14,
+            2,
+            [None, None, None, Some(2), None, None],
+        )
+        .unwrap();
+        md.try_log(
+            VoxelChange::Broken,
+            13,
+            2,
+            [None, None, Some(2), None, None, None],
+        )
+        .unwrap();
+        assert_eq!(md.changed_voxels.len(), 3);
+        assert!(matches!(md.changed_voxels[2].2, VoxelChange::Broken));
+        assert_eq!(md.is_occupied(13), Some(false));
+
+        // A voxel that was in the grid before isn't cancelled out either.
+        md.try_log(VoxelChange::Broken, 0, 1, [None; 6]).unwrap();
+        assert_eq!(md.changed_voxels.len(), 4);
+    }
+
+    #[test]
+    fn impossible_changes_are_rejected() {
+        let mut md = metadata();
+        assert_eq!(
+            md.try_log(VoxelChange::Added, 0, 1, [None; 6]),
+            Err(MeshemError::VoxelAlreadyExists(0))
+        );
+        assert_eq!(
+            md.try_log(VoxelChange::Broken, 1, 1, [None; 6]),
+            Err(MeshemError::VoxelDoesNotExist(1))
+        );
+        assert_eq!(
+            md.try_log(VoxelChange::Replaced { old: 1 }, 1, 2, [None; 6]),
+            Err(MeshemError::VoxelDoesNotExist(1))
+        );
+        assert_eq!(
+            md.try_log(VoxelChange::Added, 27, 1, [None; 6]),
+            Err(MeshemError::VoxelIndexOutOfBounds { index: 27, len: 27 })
+        );
+        // Nothing was logged, and the occupancy didn't change.
+        assert!(md.changed_voxels.is_empty());
+        assert_eq!(md.is_occupied(0), Some(true));
+        assert_eq!(md.is_occupied(1), Some(false));
+
+        // Breaking a voxel twice.
+        md.try_log(VoxelChange::Broken, 0, 1, [None; 6]).unwrap();
+        assert_eq!(
+            md.try_log(VoxelChange::Broken, 0, 1, [None; 6]),
+            Err(MeshemError::VoxelDoesNotExist(0))
+        );
+        assert_eq!(md.changed_voxels.len(), 1);
     }
 }
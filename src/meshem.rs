@@ -3,8 +3,8 @@ use crate::pbs::*;
 use crate::prelude::*;
 use bevy::asset::RenderAssetUsages;
 use bevy::prelude::*;
-use bevy::mesh::{Indices, MeshVertexAttribute, VertexAttributeValues};
-use bevy::render::render_resource::PrimitiveTopology;
+use bevy::mesh::{Indices, MeshVertexAttribute, MeshVertexAttributeId, VertexAttributeValues};
+use bevy::render::render_resource::{PrimitiveTopology, VertexFormat};
 
 /// All the variants for the Meshing algorithm.
 #[derive(Debug, Clone, Copy)]
@@ -35,7 +35,7 @@ pub enum MeshingAlgorithm {
 /// Return:
 /// - The first mesh is the mesh of the full, normal cube voxels. (for example, the stone blocks)
 /// - MeshMD<T> is the mesh metadata that the user needs to keep if they want to update the mesh.
-/// - None: Couldn't generate the mesh
+/// - None: Couldn't generate the mesh (see [`try_mesh_grid`] to find out why)
 pub fn mesh_grid<T>(
     dims: Dimensions,
     outer_layer: &[Face],
@@ -44,31 +44,257 @@ pub fn mesh_grid<T>(
     meshing_algorithm: MeshingAlgorithm,
     smooth_lighting_params: Option<SmoothLightingParameters>,
 ) -> Option<(Mesh, MeshMD<T>)> {
+    match try_mesh_grid_of(
+        dims,
+        outer_layer,
+        grid,
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    ) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("Couldn't generate the mesh: {}", e);
+            None
+        }
+    }
+}
+
+// `try_mesh_grid`, with the voxel type named through the registry, where it's always `Copy`, so
+// `mesh_grid` doesn't need the bound itself.
+fn try_mesh_grid_of<R: VoxelRegistry>(
+    dims: Dimensions,
+    outer_layer: &[Face],
+    grid: &[R::Voxel],
+    reg: &R,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<R::Voxel>), MeshemError> {
+    try_mesh_grid(
+        dims,
+        outer_layer,
+        grid,
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )
+}
+
+/// Same as [`mesh_grid`], but returns the reason the mesh couldn't be generated
+/// (see [`MeshemError`]) instead of None.
+pub fn try_mesh_grid<T: Copy>(
+    dims: Dimensions,
+    outer_layer: &[Face],
+    grid: &[T],
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    mesh_grid_with_neighbors(
+        dims,
+        outer_layer,
+        grid,
+        &AdjacentChunks::new(),
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )
+}
+
+/// Same as [`mesh_grid`], but the faces on the horizontal edges of the grid are culled against
+/// the voxels of the adjacent chunks (see [`ChunkNeighbors`]), so there is no need to call
+/// `introduce_adjacent_chunks` afterwards. If an adjacent chunk isn't available, `outer_layer`
+/// decides whether to cull that edge, same as in [`mesh_grid`].
+/// Smooth Lighting (if applied at generation) also takes the adjacent chunks into account.
+pub fn mesh_grid_with_neighbors<T: Copy>(
+    dims: Dimensions,
+    outer_layer: &[Face],
+    grid: &[T],
+    neighbors: &impl ChunkNeighbors<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    MeshemError::check_grid_size(dims, grid.len())?;
+    mesh_voxel_grid(
+        outer_layer,
+        &GridWithNeighbors {
+            grid: &FlatGrid::new(dims, grid),
+            neighbors,
+        },
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )
+}
+
+/// Same as [`mesh_grid`], but `grid` has a one voxel apron around it that holds the voxels of the
+/// adjacent chunks (see [`PaddedGrid`]), its length must be (w + 2) * (h + 2) * (l + 2), where
+/// `dims` are (w, h, l). The faces on the edges of the grid are culled against the apron, and
+/// Smooth Lighting takes it into account, so there is no need to call
+/// `introduce_adjacent_chunks` afterwards. The apron itself isn't meshed.
+pub fn mesh_grid_padded<T: Copy>(
+    dims: Dimensions,
+    grid: &[T],
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    let grid = PaddedGrid::new(dims, grid);
+    MeshemError::check_grid_size(grid.padded_dims(), grid.voxels.len())?;
+    mesh_voxel_grid(&[], &grid, reg, meshing_algorithm, smooth_lighting_params)
+}
+
+/// Mesh the box of the grid between `min` (inclusive) and `max` (exclusive), for example a
+/// building extracted from the world, without copying its voxels out. The faces on the edges of
+/// the box are culled against the voxels of the grid around it (the edges of the grid itself are
+/// treated according to `outer_layer`). The mesh is positioned relative to the origin of the
+/// box, and its metadata has the dimensions of the box.
+pub fn mesh_sub_grid<T>(
+    outer_layer: &[Face],
+    grid: &impl VoxelGrid<T>,
+    min: [usize; 3],
+    max: [usize; 3],
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    let dims = grid.dims();
+    if (0..3).any(|i| min[i] > max[i]) || max[0] > dims.0 || max[1] > dims.1 || max[2] > dims.2 {
+        return Err(MeshemError::RegionOutOfBounds { min, max, dims });
+    }
+    mesh_voxel_grid(
+        outer_layer,
+        &SubGrid { grid, min, max },
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )
+}
+
+/// Same as [`mesh_grid`], but the voxels are read from any [`VoxelGrid`], and the dimensions are
+/// taken from it. If the grid knows the voxels in its padding (see [`VoxelGrid::get_padding`]),
+/// the faces on its edges are culled against them, otherwise `outer_layer` decides.
+pub fn mesh_voxel_grid<T>(
+    outer_layer: &[Face],
+    grid: &impl VoxelGrid<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    let dims = grid.dims();
+    let occupancy = (0..dims.0 * dims.1 * dims.2)
+        .map(|i| grid.get(three_d_cords_arr(i, dims)))
+        .map(|voxel| !matches!(reg.get_mesh(&voxel), VoxelMesh::Null))
+        .collect();
+    mesh_voxels(
+        outer_layer,
+        grid,
+        0..dims.0 * dims.1 * dims.2,
+        occupancy,
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )
+}
+
+/// Mesh a sparse voxel octree, the output is the same as [`mesh_grid`] with
+/// [`MeshingAlgorithm::Culling`] on the same voxels, but empty regions of the octree are skipped
+/// entirely, and only the surface of uniform regions is visited.
+pub fn mesh_octree<T: Copy + PartialEq>(
+    outer_layer: &[Face],
+    octree: &Octree<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
+    let dims = octree.dims();
+    let mut to_visit: Vec<usize> = vec![];
+    // A region covers a run of voxels (start, length) in each of its (y, z) rows of the grid.
+    let mut occupied_rows: Vec<(usize, usize)> = vec![];
+    for (origin, size, voxel) in octree.regions() {
+        let mesh = reg.get_mesh(&voxel);
+        if matches!(mesh, VoxelMesh::Null) {
+            continue;
+        }
+        // Only normal cubes end up in the mesh.
+        let normal_cube = matches!(mesh, VoxelMesh::NormalCube(_));
+        // Inside of a region that is covered from all sides, all the faces are culled (interior
+        // quads aren't, but a voxel that covers all of its sides shouldn't have any).
+        let covered = (0..6).all(|f| is_covering_oriented(reg, &voxel, Face::from(f)))
+            && !matches!(mesh, VoxelMesh::NormalCube(m) if has_interior_quads(m));
+        let end = [
+            (origin[0] + size).min(dims.0),
+            (origin[1] + size).min(dims.1),
+            (origin[2] + size).min(dims.2),
+        ];
+        let width = end[0] - origin[0];
+        for y in origin[1]..end[1] {
+            for z in origin[2]..end[2] {
+                let start = one_d_cords([origin[0], y, z], dims);
+                occupied_rows.push((start, width));
+                if !normal_cube {
+                    continue;
+                }
+                let on_surface =
+                    y == origin[1] || y + 1 == end[1] || z == origin[2] || z + 1 == end[2];
+                if covered && !on_surface {
+                    // Only the two ends of the row are on the surface of the region.
+                    to_visit.push(start);
+                    if width > 1 {
+                        to_visit.push(start + width - 1);
+                    }
+                } else {
+                    to_visit.extend(start..start + width);
+                }
+            }
+        }
+    }
+    // The voxels must be visited in the same order as in `mesh_grid` to get the same mesh.
+    to_visit.sort_unstable();
+    occupied_rows.sort_unstable();
+    let mut occupancy = Rle::new();
+    for (start, length) in occupied_rows {
+        occupancy.push(false, start - occupancy.len());
+        occupancy.push(true, length);
+    }
+    occupancy.push(false, dims.0 * dims.1 * dims.2 - occupancy.len());
+    mesh_voxels(
+        outer_layer,
+        octree,
+        to_visit.into_iter(),
+        occupancy,
+        reg,
+        MeshingAlgorithm::Culling,
+        smooth_lighting_params,
+    )
+}
+
+/// Mesh only the voxels in `to_visit` (indices in the grid, ascending), voxels that aren't
+/// visited don't end up in the mesh. `occupancy` is which voxels of the grid aren't Null.
+fn mesh_voxels<T>(
+    outer_layer: &[Face],
+    grid: &impl VoxelGrid<T>,
+    to_visit: impl Iterator<Item = usize>,
+    occupancy: Rle<bool>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(Mesh, MeshMD<T>), MeshemError> {
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
         RenderAssetUsages::default(),
     );
-    let ch_len = grid.len();
+    let dims = grid.dims();
+    let ch_len = dims.0 * dims.1 * dims.2;
     let mut vivi = VIVI::new(ch_len);
-    assert_eq!(
-        ch_len,
-        dims.0 * dims.1 * dims.2,
-        "The product of the value of each of the dimensions must be the length of 
-        the one dimentional grid array."
-    );
 
-    let [cull_top, cull_bottom, cull_right, cull_left, cull_back, cull_forward] = {
+    let outer_layer_visible = {
         let mut r = [true, true, true, true, true, true];
         for f in outer_layer {
             r[*f as usize] = false;
         }
         r
     };
-    let width = dims.0;
-    let length = dims.2;
-    let height = dims.1;
-    let t = width * length * height;
-    let mut rle_bool_voxel = RleVec::new();
 
     let mut indices: Vec<u32> = vec![];
     let mut vertices: Vec<(MeshVertexAttribute, VertexAttributeValues)> = vec![];
@@ -78,102 +304,81 @@ pub fn mesh_grid<T>(
 
     let voxel_dims = reg.get_voxel_dimensions();
     let center = reg.get_center();
-    for k in 0..height {
-        for j in 0..length {
-            for i in 0..width {
-                let cord = k * length * width + j * width + i;
-                let above = cord + length * width;
-                let below = cord.checked_sub(width * length).unwrap_or(usize::MAX);
-                let right = cord + 1;
-                let left = cord.checked_sub(1).unwrap_or(usize::MAX);
-                let back = cord + width;
-                let forward = cord.checked_sub(width).unwrap_or(usize::MAX);
-                let mut neig = [false; 6];
-                let position_offset = (
-                    i as f32 * voxel_dims[0],
-                    k as f32 * voxel_dims[1],
-                    j as f32 * voxel_dims[2],
-                );
-
-                if in_range(k + 1, 0, height) {
-                    neig[0] = !reg.is_covering(&grid[above], Bottom);
-                } else {
-                    neig[0] = cull_top;
-                }
-                if in_range(k, 1, t) {
-                    neig[1] = !reg.is_covering(&grid[below], Top);
-                } else {
-                    neig[1] = cull_bottom;
-                }
-                if in_range(i + 1, 0, width) {
-                    neig[2] = !reg.is_covering(&grid[right], Left);
-                } else {
-                    neig[2] = cull_right;
-                }
-                if in_range(i, 1, t) {
-                    neig[3] = !reg.is_covering(&grid[left], Right);
-                } else {
-                    neig[3] = cull_left;
-                }
-                if in_range(j + 1, 0, length) {
-                    neig[4] = !reg.is_covering(&grid[back], Forward);
-                } else {
-                    neig[4] = cull_back;
-                }
-                if in_range(j, 1, t) {
-                    neig[5] = !reg.is_covering(&grid[forward], Back);
-                } else {
-                    neig[5] = cull_forward;
-                }
+    let mut oriented_meshes = OrientedMeshes::default();
+    for cord in to_visit {
+        let [i, k, j] = three_d_cords_arr(cord, dims);
+        let voxel = grid.get([i, k, j]);
+        let position_offset = (
+            i as f32 * voxel_dims[0],
+            k as f32 * voxel_dims[1],
+            j as f32 * voxel_dims[2],
+        );
 
-                match meshing_algorithm {
-                    MeshingAlgorithm::Naive => neig = [true; 6],
-                    MeshingAlgorithm::Culling => {}
-                }
+        let mut neig = [false; 6];
+        for (f, n) in neig.iter_mut().enumerate() {
+            let face = Face::from(f);
+            let [dx, dy, dz] = face.normal();
+            *n = match grid.get_signed([i as i32 + dx, k as i32 + dy, j as i32 + dz]) {
+                Some(v) => !is_covering_oriented(reg, &v, face.opposite()),
+                None => outer_layer_visible[f],
+            };
+        }
 
-                if neig == [false, false, false, false, false, false] {
-                    continue;
-                }
-                if in_range(cord, 0, t) {
-                    if let VoxelMesh::NormalCube(v_mesh) = reg.get_mesh(&grid[cord]) {
-                        // add_vertices_normal_cube() is a private function that adds the vertices and
-                        // indices to the running count of vertices and indices.
-                        add_vertices_normal_cube(
-                            neig,
-                            &mut indices,
-                            &mut vertices,
-                            v_mesh,
-                            &mut vivi,
-                            cord,
-                            center,
-                            position_offset,
-                        );
-                        rle_bool_voxel.push(true, 1);
-                    } else {
-                        rle_bool_voxel.push(false, 1);
-                    }
-                }
+        match meshing_algorithm {
+            MeshingAlgorithm::Naive => neig = [true; 6],
+            MeshingAlgorithm::Culling => {}
+        }
+
+        oriented_meshes.insert(reg, &voxel)?;
+        if let VoxelMesh::NormalCube(v_mesh) = oriented_meshes.voxel_mesh(reg, &voxel) {
+            // The interior quads of a shaped voxel are there even if all of its sides are
+            // covered.
+            if neig == [false, false, false, false, false, false] && !has_interior_quads(v_mesh) {
+                continue;
             }
+            // add_vertices_normal_cube() is a private function that adds the vertices and
+            // indices to the running count of vertices and indices.
+            add_vertices_normal_cube(
+                neig,
+                &mut indices,
+                &mut vertices,
+                v_mesh,
+                &mut vivi,
+                cord,
+                center,
+                position_offset,
+                random_uv_cords(reg.randomize_uv_rotation(&voxel), v_mesh, [i, k, j]),
+                reg.get_tint(&voxel, [i, k, j]),
+            )?;
         }
     }
 
-    for (att, vals) in vertices {
+    let reserved_quads = reg.reserved_quads();
+    indices.reserve(reserved_quads * 6);
+    for (att, mut vals) in vertices {
+        vals.reserve(reserved_quads * 4);
         mesh.insert_attribute(att, vals);
     }
     mesh.insert_indices(Indices::U32(indices));
     let d_mesh = MeshMD {
         dims,
         smooth_lighting_params,
+        outer_layer: outer_layer.to_vec(),
+        meshing_algorithm,
+        reserved_quads,
         vivi,
+        occupancy,
         changed_voxels: vec![],
+        history: None,
+        sub_meshes: None,
     };
 
     if let Some(t) = smooth_lighting_params {
         if t.apply_at_gen {
-            apply_smooth_lighting(reg, &mut mesh, &d_mesh, dims, 0, ch_len, grid);
+            apply_smooth_lighting_from_grid(reg, &mut mesh, &d_mesh, 0, ch_len, grid)?;
         }
     }
-    Some((mesh, d_mesh))
+    Ok((mesh, d_mesh))
 }
 
 /// Important helper function to add the vertices and indices of each voxel into the running count of vertices
@@ -189,17 +394,74 @@ fn add_vertices_normal_cube(
     voxel_index: usize,
     center: [f32; 3],
     position_offset: (f32, f32, f32),
-) {
+    random_uvs: Option<[usize; 3]>,
+    tint: Option<[f32; 4]>,
+) -> Result<(), MeshemError> {
     let vertices_count = vertices[0].1.len();
-    let pos_attribute = voxel
-        .attribute(Mesh::ATTRIBUTE_POSITION)
-        .expect("couldn't get voxel mesh data");
-    let VertexAttributeValues::Float32x3(positions) = pos_attribute else {
-        panic!("Unexpected vertex format for position attribute, expected Float32x3.");
-    };
-    let Indices::U32(indices) = voxel.indices().expect("couldn't get indices data") else {
-        panic!("Expected U32 indices format");
-    };
+    let quads = select_cube_quads(neig, true, voxel, center, vertices_count as u32)?;
+
+    // Collect all the attributes before changing anything, so an error doesn't leave a
+    // half-added voxel behind.
+    let mut atts = vec![];
+    for (id, _) in vertices.iter() {
+        let mut att = needed_voxel_attribute(voxel, id, &quads.vertices, position_offset)?;
+        if let Some(cords) = random_uvs {
+            rotate_uvs_randomly(&mut att, id.id, &quads, vertices_count as u32, cords);
+        }
+        if let (Some(tint), true) = (tint, id.id == Mesh::ATTRIBUTE_COLOR.id) {
+            tint_colors(&mut att, tint);
+        }
+        atts.push(att);
+    }
+    for ((_, vals), att) in vertices.iter_mut().zip(atts.iter()) {
+        vals.extend(att);
+    }
+    for (face, vertex) in quads.quads {
+        vivi.insert(face, voxel_index, vertex);
+    }
+    for (face, vertex) in quads.interior {
+        vivi.insert_interior(face, voxel_index, vertex);
+    }
+    indices_main.extend(quads.indices);
+    Ok(())
+}
+
+/// The quads of a voxel mesh that were selected to be added into a big mesh.
+pub(crate) struct CubeQuads {
+    /// The indices of the selected triangles, already offset to their place in the big mesh.
+    pub(crate) indices: Vec<u32>,
+    /// The vertices of the voxel mesh to copy, sorted by quad, each 4 vertices will be a part
+    /// of one quad, we sort them this way to efficiently update the vivi.
+    pub(crate) vertices: Vec<u32>,
+    /// The face of each quad, and the index of its first vertex in the big mesh.
+    pub(crate) quads: Vec<(Face, u32)>,
+    /// Same as `quads`, for the interior quads of a shaped voxel (see `ATTRIBUTE_FACE_TAG`).
+    pub(crate) interior: Vec<(Face, u32)>,
+}
+
+/// Select the quads of a cube-shaped voxel mesh that are facing the sides in `neig`,
+/// `vertices_count` is the amount of vertices in the mesh they are going to be added to.
+/// If the voxel mesh has an `ATTRIBUTE_FACE_TAG`, the quads are selected by their tags instead,
+/// and its interior quads are selected if `interior` is true.
+pub(crate) fn select_cube_quads(
+    neig: Neighbors,
+    interior: bool,
+    voxel: &Mesh,
+    center: [f32; 3],
+    vertices_count: u32,
+) -> Result<CubeQuads, MeshemError> {
+    if let Some(tags) = voxel.attribute(ATTRIBUTE_FACE_TAG) {
+        let VertexAttributeValues::Uint32(tags) = tags else {
+            return Err(MeshemError::UnexpectedAttributeFormat {
+                attribute: ATTRIBUTE_FACE_TAG.name,
+                expected: VertexFormat::Uint32,
+                found: tags.into(),
+            });
+        };
+        return select_tagged_quads(neig, interior, voxel, tags, vertices_count);
+    }
+    let positions = positions_of(voxel)?;
+    let indices = indices_of(voxel)?;
     let triangles = indices
         .chunks(3)
         .map(|chunk| (chunk[0], chunk[1], chunk[2]));
@@ -213,6 +475,7 @@ fn add_vertices_normal_cube(
     // the final array of the vertices, it will be sorted, each 4 vertices will be a
     // part of one quad, we sort them this way to efficiently update the vivi.
     let mut final_vertices: Vec<u32> = vec![];
+    let mut quads: Vec<(Face, u32)> = vec![];
 
     // iterate over all the triangles in the mesh
     for (a, b, c) in triangles {
@@ -286,9 +549,9 @@ fn add_vertices_normal_cube(
                     let face = vertices_to_save[i as usize].2;
                     vertices_to_save[i as usize].1 += face as u32 * 4 - offset;
                     final_vertices.push(i);
-                    // update the vivi
+                    // save the quad for the vivi
                     if only_first {
-                        vivi.insert(face, voxel_index, i + vertices_count as u32 - offset);
+                        quads.push((face, i + vertices_count - offset));
                         only_first = false;
                     }
                 }
@@ -299,18 +562,164 @@ fn add_vertices_normal_cube(
     // offset the indices, we need to consider the fact that the indices wil be part of a big mesh,
     // with a lot of vertices, so we must the vertices to a running count and offset them accordingly.
     for i in indices_to_save.iter_mut() {
-        *i = vertices_to_save[*i as usize].1 + vertices_count as u32;
+        *i = vertices_to_save[*i as usize].1 + vertices_count;
+    }
+    Ok(CubeQuads {
+        indices: indices_to_save,
+        vertices: final_vertices,
+        quads,
+        interior: vec![],
+    })
+}
+
+// Select the quads of a voxel mesh with an `ATTRIBUTE_FACE_TAG`, quad by quad. The vertices of
+// each quad are sorted the same way `select_cube_quads` sorts them, by the order they appear in
+// the triangles.
+fn select_tagged_quads(
+    neig: Neighbors,
+    interior: bool,
+    voxel: &Mesh,
+    tags: &[u32],
+    vertices_count: u32,
+) -> Result<CubeQuads, MeshemError> {
+    let indices = indices_of(voxel)?;
+    let mut r = CubeQuads {
+        indices: vec![],
+        vertices: vec![],
+        quads: vec![],
+        interior: vec![],
+    };
+    // The index of each vertex of the voxel mesh in the big mesh, if its quad was selected.
+    let mut placed: Vec<Option<u32>> = vec![None; tags.len()];
+    for t in indices.chunks_exact(3) {
+        let first = (t[0] / 4 * 4) as usize;
+        let tag = *tags.get(first).ok_or(MeshemError::VertexNotFound(first))?;
+        let face = (tag & !FACE_TAG_INTERIOR) as usize;
+        if face >= 6 {
+            return Err(MeshemError::InvalidFaceTag(tag));
+        }
+        let is_interior = tag & FACE_TAG_INTERIOR != 0;
+        if !(if is_interior { interior } else { neig[face] }) {
+            continue;
+        }
+        if placed[first].is_none() {
+            let quad = vertices_count + r.vertices.len() as u32;
+            if is_interior {
+                r.interior.push((Face::from(face), quad));
+            } else {
+                r.quads.push((Face::from(face), quad));
+            }
+            // The vertices of the first triangle of the quad, and then the rest.
+            let rest = (first as u32..first as u32 + 4).filter(|v| !t.contains(v));
+            for v in t.iter().copied().chain(rest) {
+                let slot = placed
+                    .get_mut(v as usize)
+                    .ok_or(MeshemError::VertexNotFound(v as usize))?;
+                *slot = Some(vertices_count + r.vertices.len() as u32);
+                r.vertices.push(v);
+            }
+        }
+        for &v in t {
+            let v = placed
+                .get(v as usize)
+                .copied()
+                .flatten()
+                .ok_or(MeshemError::VertexNotFound(v as usize))?;
+            r.indices.push(v);
+        }
+    }
+    Ok(r)
+}
+
+// The position of the voxel, if the textures of its quads are rotated randomly (see
+// `VoxelRegistry::randomize_uv_rotation`).
+pub(crate) fn random_uv_cords(
+    randomize: bool,
+    voxel_mesh: &Mesh,
+    cords: [usize; 3],
+) -> Option<[usize; 3]> {
+    (randomize && voxel_mesh.attribute(ATTRIBUTE_FACE_TAG).is_none()).then_some(cords)
+}
+
+// Rotate the textures of the selected quads of the voxel at `cords`, by a hash of its position
+// and the face of each quad. `vals` are the values of the attribute `id` of the selected
+// vertices, the UVs are rotated and the tangents are turned along with them, the rest stay the
+// same. The quads start at `vertices_count`.
+pub(crate) fn rotate_uvs_randomly(
+    vals: &mut VertexAttributeValues,
+    id: MeshVertexAttributeId,
+    quads: &CubeQuads,
+    vertices_count: u32,
+    cords: [usize; 3],
+) {
+    for &(face, vertex) in quads.quads.iter() {
+        let first = (vertex - vertices_count) as usize;
+        // The vertices of a selected quad go around it in this order.
+        let corners = [first, first + 1, first + 3, first + 2];
+        let steps = uv_rotation_hash(cords, face);
+        match vals {
+            VertexAttributeValues::Float32x2(uvs) if id == Mesh::ATTRIBUTE_UV_0.id => {
+                rotate_quad_uvs(uvs, corners, steps)
+            }
+            VertexAttributeValues::Float32x4(tangents) if id == Mesh::ATTRIBUTE_TANGENT.id => {
+                rotate_quad_tangents(tangents, corners, face, steps)
+            }
+            _ => return,
+        }
     }
+}
+
+// A rotation (0 - 3) for the face of the voxel at `cords`, it looks random, but it's always the
+// same for the same face.
+pub(crate) fn uv_rotation_hash(cords: [usize; 3], face: Face) -> usize {
+    let mut h = (cords[0] as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (cords[1] as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (cords[2] as u64).wrapping_mul(0x1656_67B1_9E37_79F9)
+        ^ face as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h ^= h >> 33;
+    (h & 3) as usize
+}
 
-    for (id, vals) in vertices.iter_mut() {
-        let mut att = voxel
-            .attribute(id.id)
-            .expect(format!("Couldn't retrieve voxel mesh attribute {:?}.", id).as_str())
-            .get_needed(&final_vertices);
-        if id.id == Mesh::ATTRIBUTE_POSITION.id {
-            att = att.offset_all(position_offset);
+// Multiply the colors (of the selected vertices of a voxel) by the tint of the voxel.
+pub(crate) fn tint_colors(colors: &mut VertexAttributeValues, tint: [f32; 4]) {
+    if let VertexAttributeValues::Float32x4(colors) = colors {
+        for color in colors.iter_mut() {
+            *color = std::array::from_fn(|i| color[i] * tint[i]);
         }
-        vals.extend(&att);
     }
-    indices_main.extend(indices_to_save);
+}
+
+// Whether the voxel mesh has quads that are never culled.
+pub(crate) fn has_interior_quads(voxel: &Mesh) -> bool {
+    matches!(
+        voxel.attribute(ATTRIBUTE_FACE_TAG),
+        Some(VertexAttributeValues::Uint32(tags)) if tags.iter().any(|t| t & FACE_TAG_INTERIOR != 0)
+    )
+}
+
+/// The values of `attribute` of the voxel mesh, only for the `needed` vertices (in the same
+/// order), if it's the position attribute, the values are offset by `position_offset`.
+pub(crate) fn needed_voxel_attribute(
+    voxel: &Mesh,
+    attribute: &MeshVertexAttribute,
+    needed: &Vec<u32>,
+    position_offset: (f32, f32, f32),
+) -> Result<VertexAttributeValues, MeshemError> {
+    let vals = voxel
+        .attribute(attribute.id)
+        .ok_or(MeshemError::MissingAttribute(attribute.name))?;
+    let found = VertexFormat::from(vals);
+    if found != attribute.format {
+        return Err(MeshemError::UnexpectedAttributeFormat {
+            attribute: attribute.name,
+            expected: attribute.format,
+            found,
+        });
+    }
+    if attribute.id == Mesh::ATTRIBUTE_POSITION.id {
+        return Ok(vals.get_needed(needed).offset_all(position_offset));
+    }
+    Ok(vals.get_needed(needed))
 }
@@ -0,0 +1,283 @@
+//! This module contains the orientation of voxels, one of the 24 rotations of a cube, so
+//! directional voxels (furnaces, logs, stairs...) don't need a mesh for every way they can face.
+//! The mesh of an oriented voxel is rotated before it's meshed, and `is_covering` is asked about
+//! the sides of the voxel before it was rotated.
+use crate::prelude::*;
+use bevy::mesh::{Indices, VertexAttributeValues};
+use bevy::prelude::*;
+use bevy::render::render_resource::VertexFormat;
+
+/// A rotation of a voxel, given by the sides its top and forward faces end up facing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Orientation {
+    up: Face,
+    forward: Face,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Orientation::IDENTITY
+    }
+}
+
+impl Orientation {
+    /// No rotation.
+    pub const IDENTITY: Orientation = Orientation {
+        up: Face::Top,
+        forward: Face::Forward,
+    };
+
+    /// The rotation that turns the top of the voxel to `up` and its forward face to `forward`,
+    /// None if they aren't perpendicular.
+    pub fn new(up: Face, forward: Face) -> Option<Self> {
+        (up != forward && up != forward.opposite()).then_some(Orientation { up, forward })
+    }
+
+    /// The rotation that turns the forward face of the voxel to `forward`, like a furnace facing
+    /// the player. Around the y axis if `forward` is horizontal, otherwise the voxel is tipped
+    /// over its side.
+    pub fn facing(forward: Face) -> Self {
+        let up = match forward {
+            Face::Top => Face::Back,
+            Face::Bottom => Face::Forward,
+            _ => Face::Top,
+        };
+        Orientation { up, forward }
+    }
+
+    /// The rotation that turns the top of the voxel to `up`, like a log lying along an axis.
+    pub fn pointing(up: Face) -> Self {
+        let forward = match up {
+            Face::Top | Face::Right | Face::Left => Face::Forward,
+            Face::Bottom => Face::Back,
+            Face::Back => Face::Top,
+            Face::Forward => Face::Bottom,
+        };
+        Orientation { up, forward }
+    }
+
+    /// All the 24 rotations of a cube.
+    pub fn all() -> impl Iterator<Item = Orientation> {
+        (0..6).flat_map(|up| (0..6).filter_map(move |f| Self::new(Face::from(up), Face::from(f))))
+    }
+
+    /// The side the top face of the voxel is facing.
+    pub fn up(&self) -> Face {
+        self.up
+    }
+
+    /// The side the forward face of the voxel is facing.
+    pub fn forward(&self) -> Face {
+        self.forward
+    }
+
+    /// The side `face` of the voxel is facing after it's rotated.
+    pub fn rotate_face(&self, face: Face) -> Face {
+        face_from_normal(self.rotate(face.normal()))
+    }
+
+    /// The face of the voxel that ends up facing `side` after it's rotated.
+    pub fn unrotate_face(&self, side: Face) -> Face {
+        let [x, y, z] = self.columns();
+        let n = side.normal();
+        let dot = |c: [i32; 3]| c[0] * n[0] + c[1] * n[1] + c[2] * n[2];
+        face_from_normal([dot(x), dot(y), dot(z)])
+    }
+
+    // The rotation matrix, by columns, where the x, y and z axes end up.
+    fn columns(&self) -> [[i32; 3]; 3] {
+        let y = self.up.normal();
+        let z = self.forward.opposite().normal();
+        let x = [
+            y[1] * z[2] - y[2] * z[1],
+            y[2] * z[0] - y[0] * z[2],
+            y[0] * z[1] - y[1] * z[0],
+        ];
+        [x, y, z]
+    }
+
+    fn rotate(&self, v: [i32; 3]) -> [i32; 3] {
+        let [x, y, z] = self.columns();
+        std::array::from_fn(|a| x[a] * v[0] + y[a] * v[1] + z[a] * v[2])
+    }
+
+    // The matrix only has 0s and 1s (and -1s) in it, so the rotated values are exact.
+    fn rotate_f32(&self, v: [f32; 3]) -> [f32; 3] {
+        let [x, y, z] = self.columns().map(|c| c.map(|n| n as f32));
+        std::array::from_fn(|a| x[a] * v[0] + y[a] * v[1] + z[a] * v[2])
+    }
+}
+
+fn face_from_normal(normal: [i32; 3]) -> Face {
+    (0..6)
+        .map(Face::from)
+        .find(|f| f.normal() == normal)
+        .expect("The normal of a rotated face is always the normal of a face")
+}
+
+/// Rotate a voxel mesh (a cube generated with [`generate_voxel_mesh`], or a shape cut out of it
+/// with [`generate_shape_mesh`]) around `center`. The positions, normals and tangents are
+/// rotated, and the quads are re-sorted (or re-tagged) by the sides they are facing now, the
+/// UVs stay the same, so each texture goes with its face.
+/// Rotations that swap the axes of the voxel expect its dimensions to be equal on those axes.
+pub fn rotate_voxel_mesh(
+    voxel: &Mesh,
+    orientation: Orientation,
+    center: [f32; 3],
+) -> Result<Mesh, MeshemError> {
+    let mut mesh = voxel.clone();
+    if orientation == Orientation::IDENTITY {
+        return Ok(mesh);
+    }
+    let tagged = mesh.attribute(ATTRIBUTE_FACE_TAG).is_some();
+    let vertices = mesh.count_vertices();
+    if !tagged && vertices != 24 {
+        return Err(MeshemError::UnexpectedVertexCount {
+            expected: 24,
+            found: vertices,
+        });
+    }
+    for (attribute, vals) in mesh.attributes_mut() {
+        let id = attribute.id;
+        if id == Mesh::ATTRIBUTE_POSITION.id || id == Mesh::ATTRIBUTE_NORMAL.id {
+            let VertexAttributeValues::Float32x3(vals) = vals else {
+                return Err(MeshemError::UnexpectedAttributeFormat {
+                    attribute: attribute.name,
+                    expected: VertexFormat::Float32x3,
+                    found: VertexFormat::from(&*vals),
+                });
+            };
+            let origin = if id == Mesh::ATTRIBUTE_POSITION.id {
+                center
+            } else {
+                [0.0; 3]
+            };
+            for v in vals.iter_mut() {
+                let r = orientation.rotate_f32(std::array::from_fn(|a| v[a] - origin[a]));
+                *v = std::array::from_fn(|a| r[a] + origin[a]);
+            }
+        } else if id == Mesh::ATTRIBUTE_TANGENT.id {
+            let VertexAttributeValues::Float32x4(vals) = vals else {
+                return Err(MeshemError::UnexpectedAttributeFormat {
+                    attribute: attribute.name,
+                    expected: VertexFormat::Float32x4,
+                    found: VertexFormat::from(&*vals),
+                });
+            };
+            for v in vals.iter_mut() {
+                let [x, y, z] = orientation.rotate_f32([v[0], v[1], v[2]]);
+                *v = [x, y, z, v[3]];
+            }
+        } else if id == ATTRIBUTE_FACE_TAG.id {
+            let VertexAttributeValues::Uint32(tags) = vals else {
+                return Err(MeshemError::UnexpectedAttributeFormat {
+                    attribute: attribute.name,
+                    expected: VertexFormat::Uint32,
+                    found: VertexFormat::from(&*vals),
+                });
+            };
+            for tag in tags.iter_mut() {
+                let face = *tag & !FACE_TAG_INTERIOR;
+                if face >= 6 {
+                    return Err(MeshemError::InvalidFaceTag(*tag));
+                }
+                let rotated = orientation.rotate_face(Face::from(face as usize));
+                *tag = rotated as u32 | (*tag & FACE_TAG_INTERIOR);
+            }
+        }
+    }
+    if tagged {
+        return Ok(mesh);
+    }
+
+    // The quads of a cube are sorted by face, 4 vertices for each face, so the quad that faces a
+    // side now has to be moved to the place of that side.
+    let mut order: Vec<u32> = vec![0; 24];
+    let mut moved_to: Vec<u32> = vec![0; 24];
+    for f in 0..6 {
+        let to = orientation.rotate_face(Face::from(f)) as usize;
+        for v in 0..4 {
+            order[to * 4 + v] = (f * 4 + v) as u32;
+            moved_to[f * 4 + v] = (to * 4 + v) as u32;
+        }
+    }
+    for (_, vals) in mesh.attributes_mut() {
+        *vals = vals.get_needed(&order);
+    }
+    let mut triangles: Vec<[u32; 3]> = vec![];
+    for t in indices_of(&mesh)?.chunks_exact(3) {
+        let mut moved = [0; 3];
+        for (m, &v) in moved.iter_mut().zip(t) {
+            *m = *moved_to
+                .get(v as usize)
+                .ok_or(MeshemError::VertexNotFound(v as usize))?;
+        }
+        triangles.push(moved);
+    }
+    // Keep the triangles of each quad together, in the order of the faces.
+    triangles.sort_by_key(|t| t[0] / 4);
+    mesh.insert_indices(Indices::U32(triangles.concat()));
+    Ok(mesh)
+}
+
+// Whether the voxel covers its neighbor on `side`, after it was rotated by its orientation.
+pub(crate) fn is_covering_oriented<T>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    voxel: &T,
+    side: Face,
+) -> bool {
+    reg.is_covering(voxel, reg.get_orientation(voxel).unrotate_face(side))
+}
+
+// The rotated voxel meshes, so meshing a grid (or updating a mesh) only rotates the mesh of each
+// voxel once. They are looked up by the voxel itself (voxels aren't `Hash`), there are only as many
+// as there are rotated voxel types in the mesh.
+pub(crate) struct OrientedMeshes<R: VoxelRegistry> {
+    meshes: Vec<(R::Voxel, Orientation, Mesh)>,
+}
+
+impl<R: VoxelRegistry> Default for OrientedMeshes<R> {
+    fn default() -> Self {
+        OrientedMeshes { meshes: vec![] }
+    }
+}
+
+impl<R: VoxelRegistry> OrientedMeshes<R> {
+    fn find(&self, voxel: &R::Voxel, orientation: Orientation) -> Option<&Mesh> {
+        self.meshes
+            .iter()
+            .find(|(v, o, _)| v == voxel && *o == orientation)
+            .map(|(_, _, mesh)| mesh)
+    }
+
+    // Rotate the mesh of the voxel by its orientation, if it wasn't rotated yet, so it can be
+    // borrowed with `voxel_mesh` (along with the meshes of other voxels).
+    pub(crate) fn insert(&mut self, reg: &R, voxel: &R::Voxel) -> Result<(), MeshemError> {
+        let orientation = reg.get_orientation(voxel);
+        if orientation == Orientation::IDENTITY || self.find(voxel, orientation).is_some() {
+            return Ok(());
+        }
+        if let VoxelMesh::NormalCube(mesh) | VoxelMesh::CustomMesh(mesh) = reg.get_mesh(voxel) {
+            let rotated = rotate_voxel_mesh(mesh, orientation, reg.get_center())?;
+            self.meshes.push((*voxel, orientation, rotated));
+        }
+        Ok(())
+    }
+
+    // The mesh of the voxel, rotated by its orientation. Unless the voxel isn't rotated, it must
+    // have been inserted first.
+    pub(crate) fn voxel_mesh<'a>(&'a self, reg: &'a R, voxel: &R::Voxel) -> VoxelMesh<&'a Mesh> {
+        let orientation = reg.get_orientation(voxel);
+        let rotated = |mesh: &'a Mesh| match orientation == Orientation::IDENTITY {
+            true => mesh,
+            false => self
+                .find(voxel, orientation)
+                .expect("The voxel's mesh wasn't rotated."),
+        };
+        match reg.get_mesh(voxel) {
+            VoxelMesh::NormalCube(mesh) => VoxelMesh::NormalCube(rotated(mesh)),
+            VoxelMesh::CustomMesh(mesh) => VoxelMesh::CustomMesh(rotated(mesh)),
+            VoxelMesh::Null => VoxelMesh::Null,
+        }
+    }
+}
@@ -2,6 +2,7 @@
 /// voxel based games that resembles Ambient Occlusion, but it is static- which means the
 /// shadows are computed only once, when the mesh is generated (or updated).
 use crate::prelude::*;
+use bevy::render::render_resource::VertexFormat;
 use bevy::{math::Vec3, mesh::VertexAttributeValues};
 use std::sync::{Arc, RwLock};
 
@@ -23,24 +24,16 @@ pub struct SmoothLightingParameters {
 
 pub(crate) fn apply_sl_quad(
     mesh: &mut Mesh,
-    vivi: &VIVI,
+    quad: u32,
     index: usize,
     face: Face,
     surrounding_blocks: [bool; 3 * 3 * 3],
     slparams: SmoothLightingParameters,
     voxel_dims: [f32; 3],
     dims: Dimensions,
-) {
-    let quad = vivi
-        .get_quad_index(face, index)
-        .expect("Couldn't find quad in vivi for smooth lighting");
-
-    let positions = mesh
-        .attribute(Mesh::ATTRIBUTE_POSITION)
-        .expect("Cannot apply proximity-based-shadowing without the color attribute present");
-    let VertexAttributeValues::Float32x3(positions) = positions else {
-        panic!("Unexpected Format for the position attribute")
-    };
+    tint: [f32; 4],
+) -> Result<(), MeshemError> {
+    let positions = positions_of(mesh)?;
     let ddd = three_d_cords(index, dims);
     let voxel_center = Vec3::from([
         ddd.0 as f32 * voxel_dims[0],
@@ -56,9 +49,13 @@ pub(crate) fn apply_sl_quad(
     };
     let colors = mesh
         .attribute_mut(Mesh::ATTRIBUTE_COLOR)
-        .expect("Cannot apply proximity-based-shadowing without the color attribute present");
+        .ok_or(MeshemError::MissingAttribute(Mesh::ATTRIBUTE_COLOR.name))?;
     let VertexAttributeValues::Float32x4(ref mut colors) = colors else {
-        panic!("Unexpected Format for the color attribute")
+        return Err(MeshemError::UnexpectedAttributeFormat {
+            attribute: Mesh::ATTRIBUTE_COLOR.name,
+            expected: VertexFormat::Float32x4,
+            found: VertexFormat::from(&*colors),
+        });
     };
 
     let og: [i32; 3] = match face {
@@ -104,10 +101,13 @@ pub(crate) fn apply_sl_quad(
         total = total.min(2.0);
         let color = total * slparams.intensity;
         let color = (1.0 - color.min(1.0).powf(slparams.smoothing)).max(1.0 - slparams.max);
-        colors[ver as usize] = [color, color, color, 1.0]
+        colors[ver as usize] = [color * tint[0], color * tint[1], color * tint[2], tint[3]]
     }
+    Ok(())
 }
 
+/// Apply Smooth Lighting to the voxels between `lower_bound` and `upper_bound` (indices in the
+/// grid). Panics if it couldn't be applied, see [`try_apply_smooth_lighting`].
 pub fn apply_smooth_lighting<T>(
     reg: &impl VoxelRegistry<Voxel = T>,
     mesh: &mut Mesh,
@@ -117,7 +117,7 @@ pub fn apply_smooth_lighting<T>(
     upper_bound: usize,
     this_chunk: &[T],
 ) {
-    apply_smooth_lighting_with_connected_chunks(
+    if let Err(e) = with_chunks(
         reg,
         mesh,
         metadata,
@@ -125,17 +125,41 @@ pub fn apply_smooth_lighting<T>(
         lower_bound,
         upper_bound,
         this_chunk,
-        None,
-        None,
-        None,
-        None,
-        None,
-        None,
-        None,
-        None,
-    );
+        [None; 8],
+    ) {
+        panic!("Couldn't apply smooth lighting: {}", e);
+    }
 }
 
+/// Same as [`apply_smooth_lighting`], but returns an error instead of panicking.
+pub fn try_apply_smooth_lighting<T: Copy>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    mesh: &mut Mesh,
+    metadata: &MeshMD<T>,
+    dims: Dimensions,
+    lower_bound: usize,
+    upper_bound: usize,
+    this_chunk: &[T],
+) -> Result<(), MeshemError> {
+    apply_smooth_lighting_with_neighbors(
+        reg,
+        mesh,
+        metadata,
+        dims,
+        lower_bound,
+        upper_bound,
+        this_chunk,
+        &AdjacentChunks::new(),
+    )
+}
+
+/// Wraps [`apply_smooth_lighting_with_neighbors`], with the 8 surrounding chunks (in the order of
+/// [`Direction`]) gathered into [`AdjacentChunks`]. Panics if Smooth Lighting couldn't be applied.
+/// Pass the surrounding chunks as any [`ChunkNeighbors`] instead, or read them from a
+/// [`VoxelGrid`] with [`apply_smooth_lighting_from_grid`].
+#[deprecated(
+    note = "use `apply_smooth_lighting_with_neighbors` with `AdjacentChunks` (or any `ChunkNeighbors`), or `apply_smooth_lighting_from_grid`"
+)]
 pub fn apply_smooth_lighting_with_connected_chunks<'a, T>(
     reg: &impl VoxelRegistry<Voxel = T>,
     mesh: &mut Mesh,
@@ -153,194 +177,60 @@ pub fn apply_smooth_lighting_with_connected_chunks<'a, T>(
     so_east_chunk: Option<&'a [T]>,
     so_west_chunk: Option<&'a [T]>,
 ) {
-    if let Some(sl) = metadata.smooth_lighting_params {
-        for (index, quads) in metadata.vivi.vivi.iter().enumerate().skip(lower_bound) {
-            if index > upper_bound {
-                break;
-            }
-            for q in quads {
-                let mut surrounding_blocks = [false; 3 * 3 * 3];
-                let cage_dims = (3, 3, 3);
-                let face = face_from_u32(q & REVERSE_OFFSET_CONST);
-
-                if (matches!(face, Bottom) || matches!(face, Top))
-                    && is_block_on_edge(dims, index, face)
-                {
-                    continue;
-                }
-                let (neighbor, chunk_dir) = {
-                    if is_block_on_edge(dims, index, face) {
-                        (
-                            get_neigbhor_across_chunk_safe(dims, index, face),
-                            Some(crate::util::Direction::from(face)),
-                        )
-                    } else {
-                        (Some(get_neighbor(index, face, dims)).unwrap(), None)
-                    }
-                };
-                let neighbor = if neighbor.is_some() {
-                    neighbor.unwrap()
-                } else {
-                    continue;
-                };
-                // if reg.is_covering(&this_chunk[neighbor], face.opposite()) { continue; }
-
-                let og_index_in_cage: [i32; 3] = match face {
-                    Top => [0, -1, 0],
-                    Bottom => [0, 1, 0],
-                    Right => [-1, 0, 0],
-                    Left => [1, 0, 0],
-                    Back => [0, 0, -1],
-                    Forward => [0, 0, 1],
-                };
-                let [og_x, og_y, og_z] = og_index_in_cage;
-
-                for y in -1..=1 {
-                    for z in -1..=1 {
-                        for x in -1..=1 {
-                            if (og_x == x && og_y == y)
-                                || (og_x == x && og_z == z)
-                                || (og_y == y && og_z == z)
-                            {
-                                continue;
-                            }
-                            if (og_x == x && og_x != 0)
-                                || (og_y == y && og_y != 0)
-                                || (og_z == z && og_z != 0)
-                            {
-                                continue;
-                            }
-                            if (og_x == x + 2 && og_x != 0)
-                                || (og_y == y + 2 && og_y != 0)
-                                || (og_z == z + 2 && og_z != 0)
-                            {
-                                continue;
-                            }
-                            if (og_x == x - 2 && og_x != 0)
-                                || (og_y == y - 2 && og_y != 0)
-                                || (og_z == z - 2 && og_z != 0)
-                            {
-                                continue;
-                            }
-
-                            let cage_index = one_d_cords(
-                                [(x + 1) as usize, (y + 1) as usize, (z + 1) as usize],
-                                cage_dims,
-                            );
-                            let faces = [y < 0, y > 0, x < 0, x > 0, z < 0, z > 0];
-
-                            match get_block_n_away(dims, neighbor, x, y, z) {
-                                None => {
-                                    continue;
-                                }
-                                Some((dir, neighbor_index)) => {
-                                    let final_dir = crate::prelude::util::Direction::add_direction(
-                                        chunk_dir, dir,
-                                    );
-                                    // if chunk_dir.is_some() && dir.is_some() {
-                                    //     dbg!(final_dir, chunk_dir.unwrap(), dir.unwrap());
-                                    // }
-                                    surrounding_blocks[cage_index] = match final_dir {
-                                        None => covering_multiple_faces(
-                                            reg,
-                                            &this_chunk[neighbor_index],
-                                            faces,
-                                        ),
-                                        Some(North) if north_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &north_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(South) if south_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &south_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(East) if east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &east_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(West) if west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &west_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(NoEast) if no_east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &no_east_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(NoWest) if no_west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &no_west_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(SoEast) if so_east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &so_east_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(SoWest) if so_west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &so_west_chunk.unwrap()[neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        _ => false,
-                                    };
-                                }
-                            }
-                        }
-                    }
-                }
-                apply_sl_quad(
-                    mesh,
-                    &metadata.vivi,
-                    index,
-                    face,
-                    surrounding_blocks,
-                    sl,
-                    reg.get_voxel_dimensions(),
-                    dims,
-                )
-            }
-        }
+    if let Err(e) = with_chunks(
+        reg,
+        mesh,
+        metadata,
+        dims,
+        lower_bound,
+        upper_bound,
+        this_chunk,
+        [
+            north_chunk,
+            south_chunk,
+            east_chunk,
+            west_chunk,
+            no_east_chunk,
+            no_west_chunk,
+            so_east_chunk,
+            so_west_chunk,
+        ],
+    ) {
+        panic!("Couldn't apply smooth lighting: {}", e);
     }
 }
 
-fn covering_multiple_faces<T>(
-    reg: &impl VoxelRegistry<Voxel = T>,
-    voxel: &T,
-    faces: [bool; 6],
-) -> bool {
-    for (i, b) in faces.iter().enumerate() {
-        if !*b {
-            continue;
-        }
-        if !reg.is_covering(voxel, Face::from(i)) {
-            // dbg!(2);
-            return false;
-        }
-    }
-    true
+// `apply_smooth_lighting_with_neighbors` with the surrounding chunks as slices, the voxel type is
+// named through the registry, where it's always `Copy`, so the functions that were there before
+// `ChunkNeighbors` don't need the bound themselves.
+#[allow(clippy::too_many_arguments)]
+fn with_chunks<R: VoxelRegistry>(
+    reg: &R,
+    mesh: &mut Mesh,
+    metadata: &MeshMD<R::Voxel>,
+    dims: Dimensions,
+    lower_bound: usize,
+    upper_bound: usize,
+    this_chunk: &[R::Voxel],
+    chunks: [Option<&[R::Voxel]>; 8],
+) -> Result<(), MeshemError> {
+    apply_smooth_lighting_with_neighbors(
+        reg,
+        mesh,
+        metadata,
+        dims,
+        lower_bound,
+        upper_bound,
+        this_chunk,
+        &AdjacentChunks { chunks },
+    )
 }
 
+/// Same as [`apply_smooth_lighting_with_connected_chunks`], for chunks that are shared between
+/// threads, gathered into [`AdjacentArcChunks`].
+#[deprecated(
+    note = "use `apply_smooth_lighting_with_neighbors` with `AdjacentArcChunks::read` (or any `ChunkNeighbors`), or `apply_smooth_lighting_from_grid`"
+)]
 pub fn apply_smooth_lighting_with_connected_chunks_arc<T, const N: usize>(
     reg: &impl VoxelRegistry<Voxel = T>,
     mesh: &mut Mesh,
@@ -358,38 +248,125 @@ pub fn apply_smooth_lighting_with_connected_chunks_arc<T, const N: usize>(
     so_east_chunk: Option<&Arc<RwLock<[T; N]>>>,
     so_west_chunk: Option<&Arc<RwLock<[T; N]>>>,
 ) {
-    // let north_chunk = &*north_chunk.unwrap().read().unwrap();
+    if let Err(e) = with_arc_chunks(
+        reg,
+        mesh,
+        metadata,
+        dims,
+        lower_bound,
+        upper_bound,
+        this_chunk,
+        AdjacentArcChunks {
+            chunks: [
+                north_chunk,
+                south_chunk,
+                east_chunk,
+                west_chunk,
+                no_east_chunk,
+                no_west_chunk,
+                so_east_chunk,
+                so_west_chunk,
+            ],
+        },
+    ) {
+        panic!("Couldn't apply smooth lighting: {}", e);
+    }
+}
+
+// Same as `with_chunks`, for chunks that are shared between threads.
+#[allow(clippy::too_many_arguments)]
+fn with_arc_chunks<R: VoxelRegistry, const N: usize>(
+    reg: &R,
+    mesh: &mut Mesh,
+    metadata: &MeshMD<R::Voxel>,
+    dims: Dimensions,
+    lower_bound: usize,
+    upper_bound: usize,
+    this_chunk: &[R::Voxel; N],
+    chunks: AdjacentArcChunks<R::Voxel, N>,
+) -> Result<(), MeshemError> {
+    apply_smooth_lighting_with_neighbors(
+        reg,
+        mesh,
+        metadata,
+        dims,
+        lower_bound,
+        upper_bound,
+        this_chunk,
+        &chunks.read()?,
+    )
+}
+
+/// Apply Smooth Lighting, the voxels from the surrounding chunks are looked up through
+/// `neighbors` (see [`ChunkNeighbors`]).
+pub fn apply_smooth_lighting_with_neighbors<T: Copy>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    mesh: &mut Mesh,
+    metadata: &MeshMD<T>,
+    dims: Dimensions,
+    lower_bound: usize,
+    upper_bound: usize,
+    this_chunk: &[T],
+    neighbors: &impl ChunkNeighbors<T>,
+) -> Result<(), MeshemError> {
+    MeshemError::check_grid_size(dims, this_chunk.len())?;
+    apply_smooth_lighting_from_grid(
+        reg,
+        mesh,
+        metadata,
+        lower_bound,
+        upper_bound,
+        &GridWithNeighbors {
+            grid: &FlatGrid::new(dims, this_chunk),
+            neighbors,
+        },
+    )
+}
+
+/// Apply Smooth Lighting, the voxels are read from any [`VoxelGrid`], voxels outside of the grid
+/// are read from its padding (see [`VoxelGrid::get_padding`]).
+/// All the other Smooth Lighting functions end up here.
+pub fn apply_smooth_lighting_from_grid<T>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    mesh: &mut Mesh,
+    metadata: &MeshMD<T>,
+    lower_bound: usize,
+    upper_bound: usize,
+    grid: &impl VoxelGrid<T>,
+) -> Result<(), MeshemError> {
+    let dims = grid.dims();
     if let Some(sl) = metadata.smooth_lighting_params {
         for (index, quads) in metadata.vivi.vivi.iter().enumerate().skip(lower_bound) {
             if index > upper_bound {
                 break;
             }
+            let [vx, vy, vz] = three_d_cords_arr(index, dims);
+            // Smooth Lighting is multiplied by the tint of the voxel, instead of replacing it.
+            let tint = if quads.is_empty() {
+                None
+            } else {
+                reg.get_tint(&grid.get([vx, vy, vz]), [vx, vy, vz])
+            };
             for q in quads {
+                // Interior quads of shaped voxels keep their colors.
+                if metadata.vivi.is_interior(q & OFFSET_CONST) {
+                    continue;
+                }
                 let mut surrounding_blocks = [false; 3 * 3 * 3];
                 let cage_dims = (3, 3, 3);
                 let face = face_from_u32(q & REVERSE_OFFSET_CONST);
+                let [fx, fy, fz] = face.normal();
+                // The position of the voxel the quad is facing.
+                let neighbor = [vx as i32 + fx, vy as i32 + fy, vz as i32 + fz];
 
+                // Vertical chunks aren't supported, so unless the grid knows what's above (or
+                // below) it, the quads on the vertical edges are left as they are.
                 if (matches!(face, Bottom) || matches!(face, Top))
                     && is_block_on_edge(dims, index, face)
+                    && grid.get_padding(neighbor).is_none()
                 {
                     continue;
                 }
-                let (neighbor, chunk_dir) = {
-                    if is_block_on_edge(dims, index, face) {
-                        (
-                            get_neigbhor_across_chunk_safe(dims, index, face),
-                            Some(crate::util::Direction::from(face)),
-                        )
-                    } else {
-                        (get_neighbor(index, face, dims), None)
-                    }
-                };
-                let neighbor = if neighbor.is_some() {
-                    neighbor.unwrap()
-                } else {
-                    continue;
-                };
-                // if reg.is_covering(&this_chunk[neighbor], face.opposite()) { continue; }
 
                 let og_index_in_cage: [i32; 3] = match face {
                     Top => [0, -1, 0],
@@ -435,110 +412,41 @@ pub fn apply_smooth_lighting_with_connected_chunks_arc<T, const N: usize>(
                             );
                             let faces = [y < 0, y > 0, x < 0, x > 0, z < 0, z > 0];
 
-                            match get_block_n_away(dims, neighbor, x, y, z) {
-                                None => {
-                                    continue;
-                                }
-                                Some((dir, neighbor_index)) => {
-                                    let final_dir = crate::prelude::util::Direction::add_direction(
-                                        chunk_dir, dir,
-                                    );
-                                    // if chunk_dir.is_some() && dir.is_some() {
-                                    //     dbg!(final_dir, chunk_dir.unwrap(), dir.unwrap());
-                                    // }
-                                    surrounding_blocks[cage_index] = match final_dir {
-                                        None => covering_multiple_faces(
-                                            reg,
-                                            &this_chunk[neighbor_index],
-                                            faces,
-                                        ),
-                                        // Some(North) => covering_multiple_faces(
-                                        //     reg,
-                                        //     &north_chunk[neighbor_index],
-                                        //     faces,
-                                        // ),
-                                        Some(North) if north_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &north_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(South) if south_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &south_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(East) if east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &east_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(West) if west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &west_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(NoEast) if no_east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &no_east_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(NoWest) if no_west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &no_west_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(SoEast) if so_east_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &so_east_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        Some(SoWest) if so_west_chunk.is_some() => {
-                                            covering_multiple_faces(
-                                                reg,
-                                                &so_west_chunk.unwrap().read().unwrap()
-                                                    [neighbor_index],
-                                                faces,
-                                            )
-                                        }
-                                        _ => false,
-                                    };
-                                }
-                            }
+                            surrounding_blocks[cage_index] = grid
+                                .get_signed([neighbor[0] + x, neighbor[1] + y, neighbor[2] + z])
+                                .is_some_and(|v| covering_multiple_faces(reg, &v, faces));
                         }
                     }
                 }
                 apply_sl_quad(
                     mesh,
-                    &metadata.vivi,
+                    q & OFFSET_CONST,
                     index,
                     face,
                     surrounding_blocks,
                     sl,
                     reg.get_voxel_dimensions(),
                     dims,
-                )
+                    tint.unwrap_or([1.0; 4]),
+                )?;
             }
         }
     }
+    Ok(())
+}
+
+fn covering_multiple_faces<T>(
+    reg: &impl VoxelRegistry<Voxel = T>,
+    voxel: &T,
+    faces: [bool; 6],
+) -> bool {
+    for (i, b) in faces.iter().enumerate() {
+        if !*b {
+            continue;
+        }
+        if !is_covering_oriented(reg, voxel, Face::from(i)) {
+            return false;
+        }
+    }
+    true
 }
@@ -0,0 +1,366 @@
+//! This module contains the region edits, changes to a lot of voxels at once (filling a box,
+//! replacing the voxels in a box, carving a sphere...). They change the grid and its mesh
+//! together, and remesh the whole grid instead of updating the mesh when too many voxels change.
+use crate::prelude::*;
+
+// If more than 1/REMESH_FRACTION of the voxels in the grid change, remeshing the whole grid is
+// cheaper than updating the mesh one voxel at a time.
+const REMESH_FRACTION: usize = 16;
+
+/// An edit to a region of the grid. Boxes are given by their minimum (inclusive) and maximum
+/// (exclusive) coordinates, and are clipped to the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionEdit<T> {
+    /// Set all the voxels in the box to `voxel`.
+    Fill {
+        min: [usize; 3],
+        max: [usize; 3],
+        voxel: T,
+    },
+    /// Set the voxels in the box that are `from` to `to`.
+    Replace {
+        min: [usize; 3],
+        max: [usize; 3],
+        from: T,
+        to: T,
+    },
+    /// Set all the voxels whose center is within `radius` of `center` to `voxel`. Carving a
+    /// sphere out of the grid is setting it to an empty voxel.
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        voxel: T,
+    },
+}
+
+/// What [`edit_region`] changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegionUpdate {
+    /// The parts of the mesh that changed, after a remesh, that's all of it.
+    pub dirty: DirtyRanges,
+    /// Whether the whole grid was remeshed, instead of updating the mesh.
+    pub remeshed: bool,
+    /// The edges of the grid where the mesh may have faces that an adjacent chunk covers, the
+    /// ones that changed voxels are on, or after a remesh, all of them other than the ones in
+    /// `outer_layer`. Introduce the adjacent chunks on these sides again (with
+    /// `introduce_adjacent_chunks`) to cull them, unless the grid knows the voxels of the
+    /// adjacent chunks (see `VoxelGrid::get_padding`).
+    pub edges: Vec<Face>,
+}
+
+impl<T: Copy + PartialEq> RegionEdit<T> {
+    // The box the edit may change, clipped to the dimensions.
+    fn bounds(&self, dims: Dimensions) -> ([usize; 3], [usize; 3]) {
+        let dims = [dims.0, dims.1, dims.2];
+        let (min, max) = match *self {
+            RegionEdit::Fill { min, max, .. } | RegionEdit::Replace { min, max, .. } => (min, max),
+            RegionEdit::Sphere { center, radius, .. } => (
+                center.map(|c| (c - radius).ceil().max(0.0) as usize),
+                center.map(|c| ((c + radius).floor() + 1.0).max(0.0) as usize),
+            ),
+        };
+        (
+            std::array::from_fn(|i| min[i].min(dims[i])),
+            std::array::from_fn(|i| max[i].min(dims[i])),
+        )
+    }
+
+    // The voxel at `cords` after the edit, None if the edit doesn't change it.
+    fn apply(&self, cords: [usize; 3], voxel: T) -> Option<T> {
+        match *self {
+            RegionEdit::Fill { voxel: new, .. } => Some(new),
+            RegionEdit::Replace { from, to, .. } => (voxel == from).then_some(to),
+            RegionEdit::Sphere {
+                center,
+                radius,
+                voxel: new,
+            } => {
+                let distance_squared: f32 =
+                    (0..3).map(|i| (cords[i] as f32 - center[i]).powi(2)).sum();
+                (distance_squared <= radius * radius).then_some(new)
+            }
+        }
+    }
+}
+
+/// Apply a [`RegionEdit`] to the grid, and bring the mesh and its metadata up to date with it.
+/// If only a small part of the grid changed the mesh is updated (like with `update_mesh`),
+/// otherwise the whole grid is remeshed, with the same `outer_layer`, meshing algorithm and
+/// Smooth Lighting parameters the mesh was generated with. Either way, the mesh matches the
+/// edited grid, and the edit is saved in the history (if it's enabled) as one batch.
+/// The neighbors of the voxels on the edges of the grid are taken from the grid itself (see
+/// `VoxelGrid::get_padding`), faces that were culled against adjacent chunks with
+/// `introduce_adjacent_chunks` come back after a remesh, see [`RegionUpdate::edges`].
+/// Changes that were logged and not applied yet are applied first, the grid must already contain
+/// them. If a changed voxel doesn't agree with the metadata (for example, the grid has a voxel
+/// the metadata doesn't know of), an error is returned before the grid is changed.
+/// Meshes that were split by material can't be edited this way, [`MeshemError::SplitMesh`] is
+/// returned before anything is changed.
+pub fn edit_region<T: Copy + PartialEq + std::fmt::Debug>(
+    mesh: &mut Mesh,
+    metadata: &mut MeshMD<T>,
+    grid: &mut impl VoxelGridMut<T>,
+    edit: &RegionEdit<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+) -> Result<RegionUpdate, MeshemError> {
+    if metadata.sub_meshes.is_some() {
+        return Err(MeshemError::SplitMesh);
+    }
+    let dims = grid.dims();
+    MeshemError::check_grid_size(metadata.dims, dims.0 * dims.1 * dims.2)?;
+    let old_lengths = (mesh.count_vertices(), indices_of(mesh)?.len());
+    let mut update = RegionUpdate::default();
+    // The changes that were logged before the edit are applied (and saved in the history) as their
+    // own batch, a remesh replaces the metadata, and would drop them otherwise.
+    if !metadata.changed_voxels.is_empty() {
+        update.dirty = try_update_mesh(mesh, metadata, reg)?;
+    }
+
+    // The voxels that change, and what they change to.
+    let (min, max) = edit.bounds(dims);
+    let mut changes: Vec<([usize; 3], T)> = vec![];
+    for y in min[1]..max[1] {
+        for z in min[2]..max[2] {
+            for x in min[0]..max[0] {
+                let cords = [x, y, z];
+                let old = grid.get(cords);
+                match edit.apply(cords, old) {
+                    Some(new) if new != old => changes.push((cords, new)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    if changes.is_empty() {
+        return Ok(update);
+    }
+    let remesh = changes.len() * REMESH_FRACTION > metadata.vivi.vivi.len();
+
+    // Make sure every change can be logged before changing the grid, each voxel changes once, so
+    // it only needs to agree with the metadata.
+    let is_empty = |voxel: &T| matches!(reg.get_mesh(voxel), VoxelMesh::Null);
+    for (cords, new) in changes.iter() {
+        let index = one_d_cords(*cords, dims);
+        let occupied = !is_empty(&grid.get(*cords));
+        if metadata.is_occupied(index) == Some(occupied) {
+            continue;
+        }
+        match (occupied, is_empty(new)) {
+            // Nothing is logged.
+            (false, true) => {}
+            (true, _) => return Err(MeshemError::VoxelDoesNotExist(index)),
+            (false, false) => return Err(MeshemError::VoxelAlreadyExists(index)),
+        }
+    }
+
+    // Change the grid one voxel at a time, so the neighbors of every change are the ones it
+    // would have been logged with.
+    let mut batch = vec![];
+    for (cords, new) in changes {
+        let neighbors = grid.get_neighbors(cords);
+        let old = grid.set(cords, new);
+        let (voxel, change) = match (is_empty(&old), is_empty(&new)) {
+            (true, true) => continue,
+            (true, false) => (new, VoxelChange::Added),
+            (false, true) => (old, VoxelChange::Broken),
+            (false, false) => (new, VoxelChange::Replaced { old }),
+        };
+        let index = one_d_cords(cords, dims);
+        if remesh {
+            batch.push((voxel, index, change, neighbors));
+        } else {
+            metadata.try_log(change, index, voxel, neighbors)?;
+            for face in edges_of(cords, dims) {
+                if !update.edges.contains(&face) {
+                    update.edges.push(face);
+                }
+            }
+        }
+    }
+
+    if !remesh {
+        let dirty = try_update_mesh(mesh, metadata, reg)?;
+        update.dirty.vertices.extend(dirty.vertices);
+        update.dirty.indices.extend(dirty.indices);
+        update.dirty.finish(
+            old_lengths,
+            (mesh.count_vertices(), indices_of(mesh)?.len()),
+        );
+        return Ok(update);
+    }
+
+    let (new_mesh, mut new_metadata) = mesh_voxel_grid(
+        &metadata.outer_layer,
+        grid,
+        reg,
+        metadata.meshing_algorithm,
+        metadata.smooth_lighting_params,
+    )?;
+    // There are no logged changes left to carry over, they were applied before the edit.
+    debug_assert!(metadata.changed_voxels.is_empty());
+    new_metadata.history = metadata.history.take();
+    new_metadata.record_history(batch);
+    *mesh = new_mesh;
+    *metadata = new_metadata;
+    let new_lengths = (mesh.count_vertices(), indices_of(mesh)?.len());
+    let mut dirty = DirtyRanges::default();
+    dirty.mark_vertices(0..new_lengths.0);
+    dirty.mark_indices(0..new_lengths.1);
+    dirty.finish(old_lengths, new_lengths);
+    Ok(RegionUpdate {
+        dirty,
+        remeshed: true,
+        edges: (0..6)
+            .map(Face::from)
+            .filter(|face| !metadata.outer_layer.contains(face))
+            .collect(),
+    })
+}
+
+// The edges of the grid the voxel at `cords` is on.
+fn edges_of(cords: [usize; 3], dims: Dimensions) -> Vec<Face> {
+    let dims = [dims.0, dims.1, dims.2];
+    let mut edges = vec![];
+    for (axis, (low, high)) in [(Left, Right), (Bottom, Top), (Forward, Back)]
+        .into_iter()
+        .enumerate()
+    {
+        if cords[axis] == 0 {
+            edges.push(low);
+        }
+        if cords[axis] + 1 == dims[axis] {
+            edges.push(high);
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::prelude::*;
+
+    const DIMS: Dimensions = (8, 8, 8);
+
+    fn mesh(grid: &[u16]) -> (Mesh, MeshMD<u16>) {
+        mesh_grid(
+            DIMS,
+            &[],
+            grid,
+            &TestRegistry::new(),
+            MeshingAlgorithm::Culling,
+            None,
+        )
+        .unwrap()
+    }
+
+    // Apply the edits to a random grid one after the other, and check the mesh against a fresh
+    // mesh of the grid after each one. Returns whether each edit remeshed the grid.
+    fn edit_and_verify(seed: u64, edits: &[RegionEdit<u16>]) -> Vec<bool> {
+        let reg = TestRegistry::new();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut grid = FlatGrid::new(DIMS, random_grid(&mut rng, 512, 3));
+        let (mut mesh, mut md) = mesh(&grid.voxels);
+        edits
+            .iter()
+            .map(|edit| {
+                let update = edit_region(&mut mesh, &mut md, &mut grid, edit, &reg).unwrap();
+                let report = md.verify(&mesh, &grid, &reg);
+                assert!(report.is_ok(), "{:?}: {:?}", edit, report.issues);
+                update.remeshed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_edits_update_the_mesh() {
+        let edits = [
+            RegionEdit::Fill {
+                min: [0, 0, 0],
+                max: [2, 3, 2],
+                voxel: 2,
+            },
+            RegionEdit::Replace {
+                min: [3, 3, 3],
+                max: [6, 6, 6],
+                from: 1,
+                to: 3,
+            },
+            RegionEdit::Sphere {
+                center: [5.0, 2.0, 4.0],
+                radius: 1.5,
+                voxel: 0,
+            },
+        ];
+        assert_eq!(edit_and_verify(101, &edits), [false; 3]);
+    }
+
+    #[test]
+    fn large_edits_remesh_the_grid() {
+        let edits = [
+            RegionEdit::Fill {
+                min: [1, 0, 2],
+                max: [7, 6, 9],
+                voxel: 1,
+            },
+            RegionEdit::Replace {
+                min: [0, 0, 0],
+                max: [8, 8, 8],
+                from: 1,
+                to: 2,
+            },
+            RegionEdit::Sphere {
+                center: [4.0, 3.5, 4.0],
+                radius: 3.5,
+                voxel: 0,
+            },
+        ];
+        assert_eq!(edit_and_verify(102, &edits), [true; 3]);
+    }
+
+    #[test]
+    fn pending_changes_are_kept_by_a_remesh() {
+        let reg = TestRegistry::new();
+        let mut voxels = vec![0u16; 512];
+        voxels[..64].fill(1);
+        let (mut mesh, mut md) = mesh(&voxels);
+        md.enable_history(8);
+        let before = voxels.clone();
+
+        // Logged, but not applied yet.
+        let index = one_d_cords([3, 7, 3], DIMS);
+        voxels[index] = 3;
+        md.log(
+            VoxelChange::Added,
+            index,
+            3,
+            neighbors_of(&voxels, DIMS, index),
+        );
+        let mut grid = FlatGrid::new(DIMS, voxels);
+        let edit = RegionEdit::Fill {
+            min: [0, 2, 0],
+            max: [8, 4, 8],
+            voxel: 2,
+        };
+        assert!(
+            edit_region(&mut mesh, &mut md, &mut grid, &edit, &reg)
+                .unwrap()
+                .remeshed
+        );
+        assert!(md.verify(&mesh, &grid, &reg).is_ok());
+
+        // Undoing the edit, then the logged change, gets back to the start.
+        for batch_len in [128, 1] {
+            let edits = md.undo().unwrap();
+            assert_eq!(edits.len(), batch_len);
+            for e in edits {
+                grid.voxels[e.index] = e.new.unwrap_or(0);
+            }
+            try_update_mesh(&mut mesh, &mut md, &reg).unwrap();
+            assert!(md.verify(&mesh, &grid, &reg).is_ok());
+        }
+        assert_eq!(grid.voxels, before);
+        assert!(!md.can_undo());
+    }
+}
@@ -0,0 +1,556 @@
+//! This module contains a ready-made, data-driven [`VoxelRegistry`], loaded from an asset file
+//! (`.voxels.ron` or `.voxels.json`), so adding a voxel doesn't need recompiling. The voxels are
+//! `u16` ids, 0 is air. An example of a registry file:
+//! ```ron
+//! (
+//!     texture_atlas: Some("atlas.png"),
+//!     texture_atlas_dims: (4, 4),
+//!     padding: 0.05,
+//!     color_intensity: 0.8,
+//!     voxels: [
+//!         (id: 1, name: "stone", textures: (all: Some((0, 0)))),
+//!         (id: 2, name: "grass", textures: (top: Some((1, 0)), bottom: Some((2, 0)), sides: Some((3, 0)))),
+//!         (id: 3, name: "stone_slab", textures: (all: Some((0, 0))), shape: Some(Slab(top: false))),
+//!         (id: 4, name: "glass", textures: (all: Some((0, 1))), transparent: true, alpha: 0.5),
+//!         (id: 5, name: "lamp", textures: (all: Some((1, 1))), emission: 1.0),
+//!         (
+//!             id: 6,
+//!             name: "water",
+//!             textures: (all: Some((0, 2))),
+//!             transparent: true,
+//!             alpha: 0.8,
+//!             animation: Some((frames: [(0, 2), (1, 2), (2, 2), (3, 2)], frame_time: 0.25)),
+//!         ),
+//!     ],
+//! )
+//! ```
+use crate::prelude::*;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::mesh::MeshVertexAttribute;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The contents of a registry file, see [`VoxelRegistryAsset`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoxelRegistryDef {
+    /// The path of the texture atlas, relative to the assets folder. It's loaded with the
+    /// registry (see [`VoxelRegistryAsset::atlas`]).
+    #[serde(default)]
+    pub texture_atlas: Option<String>,
+    /// The amount of tiles in the atlas, (columns, rows).
+    pub texture_atlas_dims: [u32; 2],
+    #[serde(default = "default_voxel_dims")]
+    pub voxel_dims: [f32; 3],
+    /// The center of the voxel meshes.
+    #[serde(default)]
+    pub center: [f32; 3],
+    /// The padding of the tiles, see [`generate_voxel_mesh`].
+    #[serde(default)]
+    pub padding: f32,
+    /// The intensity of the vertex colors of the voxels (before Smooth Lighting).
+    #[serde(default = "one")]
+    pub color_intensity: f32,
+    /// Generate tangents for the voxels, to use a normal map (see [`generate_voxel_tangents`]).
+    #[serde(default)]
+    pub tangents: bool,
+    pub voxels: Vec<VoxelDef>,
+}
+
+impl VoxelRegistryDef {
+    /// Whether any voxel has an animation.
+    pub fn is_animated(&self) -> bool {
+        self.voxels.iter().any(|voxel| voxel.animation.is_some())
+    }
+}
+
+/// A voxel in a registry file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoxelDef {
+    /// The voxel in the grid, 0 is air and can't be defined.
+    pub id: u16,
+    pub name: String,
+    pub textures: FaceTextures,
+    /// The shape that is cut out of the voxel (see [`generate_shape_mesh`]), a full cube if
+    /// None.
+    #[serde(default)]
+    pub shape: Option<VoxelShape>,
+    /// The sides the voxel covers, if None, a full voxel covers all of its sides (unless it's
+    /// transparent), and a shaped one covers the sides its shape covers.
+    #[serde(default)]
+    pub covering: Option<Vec<Face>>,
+    /// Transparent voxels don't cover their neighbors (unless `covering` says so), they need a
+    /// material that blends alpha.
+    #[serde(default)]
+    pub transparent: bool,
+    /// The alpha of the vertex colors of the voxel.
+    #[serde(default = "one")]
+    pub alpha: f32,
+    /// How much light the voxel gives off (0 to 1). The crate doesn't spread light, this is for
+    /// the lighting of the game (see [`VoxelRegistryAsset::emission`]).
+    #[serde(default)]
+    pub emission: f32,
+    /// Animates the faces that are textured with the first frame (see [`TextureAnimation`]),
+    /// the meshes of the registry get an [`ATTRIBUTE_TEXTURE_ANIMATION`] if any voxel is
+    /// animated, to be drawn with [`AnimatedVoxelMaterial`].
+    #[serde(default)]
+    pub animation: Option<TextureAnimation>,
+}
+
+/// The tiles in the atlas the faces of a voxel are textured with, a face uses the first of its
+/// own tile, `sides` (for Right, Left, Back and Forward) and `all` that is given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaceTextures {
+    pub all: Option<[u32; 2]>,
+    pub sides: Option<[u32; 2]>,
+    pub top: Option<[u32; 2]>,
+    pub bottom: Option<[u32; 2]>,
+    pub right: Option<[u32; 2]>,
+    pub left: Option<[u32; 2]>,
+    pub back: Option<[u32; 2]>,
+    pub forward: Option<[u32; 2]>,
+}
+
+impl FaceTextures {
+    /// The tile of `face`, if any was given.
+    pub fn get(&self, face: Face) -> Option<[u32; 2]> {
+        let (own, side) = match face {
+            Face::Top => (self.top, false),
+            Face::Bottom => (self.bottom, false),
+            Face::Right => (self.right, true),
+            Face::Left => (self.left, true),
+            Face::Back => (self.back, true),
+            Face::Forward => (self.forward, true),
+        };
+        own.or(if side { self.sides } else { None }).or(self.all)
+    }
+}
+
+fn default_voxel_dims() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// Everything that could go wrong while loading a registry file.
+#[derive(Debug)]
+pub enum VoxelRegistryError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+    /// A voxel was defined with id 0, which is air.
+    ReservedId(String),
+    /// Two voxels were defined with the same id.
+    DuplicateId(u16),
+    /// Two voxels were defined with the same name.
+    DuplicateName(String),
+    /// No tile was given for a face of a voxel (by name).
+    MissingTexture {
+        voxel: String,
+        face: Face,
+    },
+    /// The mesh of a voxel (by name) couldn't be generated.
+    Mesh {
+        voxel: String,
+        error: MeshemError,
+    },
+}
+
+impl fmt::Display for VoxelRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Couldn't read the registry: {}", e),
+            Self::Ron(e) => write!(f, "Couldn't parse the registry: {}", e),
+            Self::Json(e) => write!(f, "Couldn't parse the registry: {}", e),
+            Self::ReservedId(name) => {
+                write!(f, "Voxel {} can't have id 0, it's reserved for air", name)
+            }
+            Self::DuplicateId(id) => write!(f, "More than one voxel has id {}", id),
+            Self::DuplicateName(name) => write!(f, "More than one voxel is named {}", name),
+            Self::MissingTexture { voxel, face } => {
+                write!(
+                    f,
+                    "Voxel {} doesn't have a texture for its {:?} face",
+                    voxel, face
+                )
+            }
+            Self::Mesh { voxel, error } => {
+                write!(
+                    f,
+                    "Couldn't generate the mesh of voxel {}: {}",
+                    voxel, error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for VoxelRegistryError {}
+
+impl From<std::io::Error> for VoxelRegistryError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for VoxelRegistryError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        Self::Ron(e)
+    }
+}
+
+impl From<serde_json::Error> for VoxelRegistryError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// A [`VoxelRegistry`] built from a [`VoxelRegistryDef`], usually loaded from a registry file
+/// (add [`VoxelRegistryPlugin`]). The meshes are generated with [`generate_voxel_mesh`] (and
+/// [`generate_shape_mesh`]), with the position, UV, normal and color attributes.
+/// With the `file_watcher` feature of bevy, the registry is reloaded when the file changes, the
+/// meshes of the grids need to be regenerated on `AssetEvent::Modified`.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct VoxelRegistryAsset {
+    def: VoxelRegistryDef,
+    /// The texture atlas, if the registry file has one.
+    #[dependency]
+    pub atlas: Option<Handle<Image>>,
+    voxels: HashMap<u16, (usize, Mesh)>,
+    names: HashMap<String, u16>,
+}
+
+impl VoxelRegistryAsset {
+    /// Generate the meshes of the voxels, the atlas isn't loaded.
+    pub fn from_def(def: VoxelRegistryDef) -> Result<Self, VoxelRegistryError> {
+        let mut voxels = HashMap::new();
+        let mut names = HashMap::new();
+        let animated = def.is_animated();
+        for (i, voxel) in def.voxels.iter().enumerate() {
+            if voxel.id == 0 {
+                return Err(VoxelRegistryError::ReservedId(voxel.name.clone()));
+            }
+            let mut textures = [(Face::Top, [0, 0]); 6];
+            for (f, texture) in textures.iter_mut().enumerate() {
+                let face = Face::from(f);
+                let tile =
+                    voxel
+                        .textures
+                        .get(face)
+                        .ok_or_else(|| VoxelRegistryError::MissingTexture {
+                            voxel: voxel.name.clone(),
+                            face,
+                        })?;
+                *texture = (face, tile);
+            }
+            let mut mesh = generate_voxel_mesh(
+                def.voxel_dims,
+                def.texture_atlas_dims,
+                textures,
+                def.center,
+                def.padding,
+                Some(def.color_intensity),
+                voxel.alpha,
+            );
+            if animated {
+                let animations: Vec<(Face, &TextureAnimation)> = match &voxel.animation {
+                    Some(animation) => textures
+                        .iter()
+                        .filter(|(_, tile)| animation.frames.first() == Some(tile))
+                        .map(|(face, _)| (*face, animation))
+                        .collect(),
+                    None => vec![],
+                };
+                animate_voxel_faces(&mut mesh, def.texture_atlas_dims, &animations).map_err(
+                    |error| VoxelRegistryError::Mesh {
+                        voxel: voxel.name.clone(),
+                        error,
+                    },
+                )?;
+            }
+            if def.tangents {
+                generate_voxel_tangents(&mut mesh).map_err(|error| VoxelRegistryError::Mesh {
+                    voxel: voxel.name.clone(),
+                    error,
+                })?;
+            }
+            if let Some(shape) = voxel.shape {
+                mesh = generate_shape_mesh(shape, &mesh).map_err(|error| {
+                    VoxelRegistryError::Mesh {
+                        voxel: voxel.name.clone(),
+                        error,
+                    }
+                })?;
+            }
+            if voxels.insert(voxel.id, (i, mesh)).is_some() {
+                return Err(VoxelRegistryError::DuplicateId(voxel.id));
+            }
+            if names.insert(voxel.name.clone(), voxel.id).is_some() {
+                return Err(VoxelRegistryError::DuplicateName(voxel.name.clone()));
+            }
+        }
+        Ok(VoxelRegistryAsset {
+            def,
+            atlas: None,
+            voxels,
+            names,
+        })
+    }
+
+    /// The definition the registry was built from.
+    pub fn def(&self) -> &VoxelRegistryDef {
+        &self.def
+    }
+
+    /// The id of the voxel named `name`.
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+
+    /// The definition of a voxel, None for air and ids that aren't defined.
+    pub fn get_def(&self, voxel: u16) -> Option<&VoxelDef> {
+        self.voxels.get(&voxel).map(|(i, _)| &self.def.voxels[*i])
+    }
+
+    pub fn is_transparent(&self, voxel: u16) -> bool {
+        self.get_def(voxel).is_some_and(|def| def.transparent)
+    }
+
+    /// How much light the voxel gives off, 0 for air and ids that aren't defined.
+    pub fn emission(&self, voxel: u16) -> f32 {
+        self.get_def(voxel).map_or(0.0, |def| def.emission)
+    }
+}
+
+impl VoxelRegistry for VoxelRegistryAsset {
+    type Voxel = u16;
+
+    fn get_mesh(&self, voxel: &u16) -> VoxelMesh<&Mesh> {
+        match self.voxels.get(voxel) {
+            Some((_, mesh)) => VoxelMesh::NormalCube(mesh),
+            None => VoxelMesh::Null,
+        }
+    }
+
+    fn is_covering(&self, voxel: &u16, side: Face) -> bool {
+        let Some(def) = self.get_def(*voxel) else {
+            return false;
+        };
+        match (&def.covering, def.shape) {
+            (Some(covering), _) => covering.contains(&side),
+            _ if def.transparent => false,
+            (None, Some(shape)) => shape.covers(side),
+            (None, None) => true,
+        }
+    }
+
+    fn get_center(&self) -> [f32; 3] {
+        self.def.center
+    }
+
+    fn get_voxel_dimensions(&self) -> [f32; 3] {
+        self.def.voxel_dims
+    }
+
+    fn all_attributes(&self) -> Vec<MeshVertexAttribute> {
+        let mut attributes = vec![
+            Mesh::ATTRIBUTE_POSITION,
+            Mesh::ATTRIBUTE_UV_0,
+            Mesh::ATTRIBUTE_NORMAL,
+            Mesh::ATTRIBUTE_COLOR,
+        ];
+        if self.def.tangents {
+            attributes.push(Mesh::ATTRIBUTE_TANGENT);
+        }
+        if self.def.is_animated() {
+            attributes.push(ATTRIBUTE_TEXTURE_ANIMATION);
+        }
+        attributes
+    }
+}
+
+/// Loads [`VoxelRegistryAsset`]s from `.voxels.ron` and `.voxels.json` files.
+#[derive(Default, TypePath)]
+pub struct VoxelRegistryLoader;
+
+impl AssetLoader for VoxelRegistryLoader {
+    type Asset = VoxelRegistryAsset;
+    type Settings = ();
+    type Error = VoxelRegistryError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        let is_json = load_context
+            .path()
+            .path()
+            .extension()
+            .is_some_and(|e| e == "json");
+        let def: VoxelRegistryDef = if is_json {
+            serde_json::from_slice(&bytes)?
+        } else {
+            ron::de::from_bytes(&bytes)?
+        };
+        let mut registry = VoxelRegistryAsset::from_def(def)?;
+        registry.atlas = registry
+            .def
+            .texture_atlas
+            .clone()
+            .map(|path| load_context.load(path));
+        Ok(registry)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["voxels.ron", "voxels.json"]
+    }
+}
+
+/// Registers [`VoxelRegistryAsset`] and its loader.
+pub struct VoxelRegistryPlugin;
+
+impl Plugin for VoxelRegistryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<VoxelRegistryAsset>()
+            .init_asset_loader::<VoxelRegistryLoader>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(ron: &str) -> Result<VoxelRegistryAsset, VoxelRegistryError> {
+        VoxelRegistryAsset::from_def(ron::de::from_str(ron)?)
+    }
+
+    // A registry with the given voxels, in an atlas of 4x4 tiles.
+    fn with_voxels(voxels: &str) -> Result<VoxelRegistryAsset, VoxelRegistryError> {
+        parse(&format!(
+            "(texture_atlas_dims: (4, 4), voxels: [{}])",
+            voxels
+        ))
+    }
+
+    #[test]
+    fn registry_is_parsed_from_ron() {
+        let reg = parse(
+            r#"(
+                texture_atlas: Some("atlas.png"),
+                texture_atlas_dims: (4, 4),
+                voxels: [
+                    (id: 1, name: "stone", textures: (all: Some((0, 0)))),
+                    (id: 2, name: "grass", textures: (top: Some((1, 0)), sides: Some((3, 0)), all: Some((2, 0)))),
+                    (id: 3, name: "stone_slab", textures: (all: Some((0, 0))), shape: Some(Slab(top: false))),
+                    (id: 4, name: "glass", textures: (all: Some((0, 1))), transparent: true, alpha: 0.5),
+                    (id: 7, name: "lamp", textures: (all: Some((1, 1))), emission: 1.0),
+                ],
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(reg.def().texture_atlas.as_deref(), Some("atlas.png"));
+        assert_eq!(reg.id("grass"), Some(2));
+        assert_eq!(reg.id("dirt"), None);
+        assert_eq!(
+            reg.get_def(2).unwrap().textures.get(Face::Bottom),
+            Some([2, 0])
+        );
+        assert_eq!(
+            reg.get_def(2).unwrap().textures.get(Face::Left),
+            Some([3, 0])
+        );
+        assert!(matches!(reg.get_mesh(&1), VoxelMesh::NormalCube(_)));
+        assert!(matches!(reg.get_mesh(&0), VoxelMesh::Null));
+        assert!(matches!(reg.get_mesh(&5), VoxelMesh::Null));
+        assert!(reg.is_covering(&1, Face::Top));
+        assert!(reg.is_covering(&3, Face::Bottom));
+        assert!(!reg.is_covering(&3, Face::Top));
+        assert!(!reg.is_covering(&4, Face::Top));
+        assert!(reg.is_transparent(4));
+        assert_eq!(reg.emission(7), 1.0);
+        assert_eq!(reg.emission(1), 0.0);
+        assert_eq!(reg.all_attributes().len(), 4);
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        assert!(matches!(
+            parse("(voxels: [)"),
+            Err(VoxelRegistryError::Ron(_))
+        ));
+        let json: Result<VoxelRegistryDef, VoxelRegistryError> =
+            serde_json::from_str("{\"voxels\": []}").map_err(Into::into);
+        assert!(matches!(json, Err(VoxelRegistryError::Json(_))));
+    }
+
+    #[test]
+    fn air_can_not_be_defined() {
+        assert!(matches!(
+            with_voxels(r#"(id: 0, name: "void", textures: (all: Some((0, 0))))"#),
+            Err(VoxelRegistryError::ReservedId(name)) if name == "void"
+        ));
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        assert!(matches!(
+            with_voxels(
+                r#"(id: 1, name: "stone", textures: (all: Some((0, 0)))),
+                   (id: 1, name: "dirt", textures: (all: Some((1, 0))))"#
+            ),
+            Err(VoxelRegistryError::DuplicateId(1))
+        ));
+    }
+
+    #[test]
+    fn names_are_unique() {
+        assert!(matches!(
+            with_voxels(
+                r#"(id: 1, name: "stone", textures: (all: Some((0, 0)))),
+                   (id: 2, name: "stone", textures: (all: Some((1, 0))))"#
+            ),
+            Err(VoxelRegistryError::DuplicateName(name)) if name == "stone"
+        ));
+    }
+
+    #[test]
+    fn every_face_needs_a_texture() {
+        assert!(matches!(
+            with_voxels(r#"(id: 1, name: "grass", textures: (top: Some((1, 0)), sides: Some((3, 0))))"#),
+            Err(VoxelRegistryError::MissingTexture { voxel, face: Face::Bottom }) if voxel == "grass"
+        ));
+    }
+
+    #[test]
+    fn mesh_errors_name_the_voxel() {
+        assert!(matches!(
+            with_voxels(
+                r#"(
+                    id: 1,
+                    name: "water",
+                    textures: (all: Some((0, 2))),
+                    animation: Some((frames: [(0, 2), (1, 2)], frame_time: 0.0)),
+                )"#
+            ),
+            Err(VoxelRegistryError::Mesh {
+                voxel,
+                error: MeshemError::InvalidTextureAnimation(Face::Top),
+            }) if voxel == "water"
+        ));
+    }
+
+    #[test]
+    fn io_errors_are_reported() {
+        let error: VoxelRegistryError = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        assert!(matches!(error, VoxelRegistryError::Io(_)));
+    }
+}
@@ -0,0 +1,538 @@
+//! This module splits the mesh of a grid into sub-meshes by the material keys of its voxels (see
+//! `VoxelRegistry::get_material_key`), so the parts of a chunk can be drawn with different
+//! materials (metallic, emissive...), while one `MeshMD` keeps track of all of them.
+//! The sub-meshes are updated in place, only the quads that changed are touched, in the
+//! sub-meshes they are in.
+use crate::prelude::*;
+use bevy::asset::RenderAssetUsages;
+use bevy::mesh::{Indices, MeshVertexAttribute, VertexAttributeValues};
+use bevy::prelude::*;
+use bevy::render::render_resource::PrimitiveTopology;
+
+/// The meshes a mesh was split into, each with the material key of its voxels.
+pub type SubMeshes = Vec<(u32, Mesh)>;
+
+// Where the quads of a mesh that was split by material are.
+pub(crate) struct SubMeshLayout {
+    // The material key of every voxel of the grid, as of the last logged change.
+    keys: Rle<u32>,
+    // The key of each sub-mesh, in the order they were returned.
+    sub_meshes: Vec<u32>,
+    // The sub-mesh each quad of the full mesh is in (by the first vertex of the quad / 4), and
+    // the first vertex of the quad in it.
+    quads: Vec<(u32, u32)>,
+    // The quad of the full mesh each quad of each sub-mesh is, the reverse of `quads`.
+    owners: Vec<Vec<u32>>,
+    // The attributes of the full mesh, so it can be put back together from no sub-meshes.
+    attributes: Vec<MeshVertexAttribute>,
+}
+
+impl SubMeshLayout {
+    // Keep the keys of the voxels up to date with the logged changes. A change adds quads of the
+    // voxel itself (unless it was broken) and of its neighbors.
+    pub(crate) fn log_keys<T>(
+        &mut self,
+        reg: &impl VoxelRegistry<Voxel = T>,
+        changes: &[LoggedChange<T>],
+        dims: Dimensions,
+    ) {
+        for (voxel, index, change, neighbors) in changes {
+            if !matches!(change, VoxelChange::Broken) {
+                self.keys.set(*index, reg.get_material_key(voxel));
+            }
+            for (f, neighbor) in neighbors.iter().enumerate() {
+                if let (Some(neighbor), Some(n)) =
+                    (neighbor, get_neighbor(*index, Face::from(f), dims))
+                {
+                    self.keys.set(n, reg.get_material_key(neighbor));
+                }
+            }
+        }
+    }
+
+    // Make sure the sub-meshes are the ones this layout is of, so they can be updated in place.
+    fn check(&self, sub_meshes: &[(u32, Mesh)]) -> Result<(), MeshemError> {
+        if sub_meshes.len() != self.sub_meshes.len()
+            || sub_meshes
+                .iter()
+                .zip(self.sub_meshes.iter().zip(self.owners.iter()))
+                .any(|((key, sub_mesh), (k, owners))| {
+                    key != k || sub_mesh.count_vertices() != owners.len() * 4
+                })
+        {
+            return Err(MeshemError::SubMeshMismatch);
+        }
+        Ok(())
+    }
+
+    // Apply the logged changes to the sub-meshes, returns the parts of each sub-mesh that
+    // changed.
+    fn update<T: std::fmt::Debug>(
+        &mut self,
+        sub_meshes: &mut SubMeshes,
+        metadata: &mut MeshMD<T>,
+        reg: &impl VoxelRegistry<Voxel = T>,
+    ) -> Result<Vec<DirtyRanges>, MeshemError> {
+        self.check(sub_meshes)?;
+        let mut old_lengths = Vec::with_capacity(sub_meshes.len());
+        for (_, sub_mesh) in sub_meshes.iter() {
+            old_lengths.push((sub_mesh.count_vertices(), indices_of(sub_mesh)?.len()));
+        }
+        self.log_keys(reg, &metadata.changed_voxels, metadata.dims);
+        let mut buffers = SubMeshBuffers {
+            dirty: vec![DirtyRanges::default(); sub_meshes.len()],
+            sub_meshes,
+            layout: self,
+        };
+        apply_logged_changes(&mut buffers, metadata, reg)?;
+        let SubMeshBuffers {
+            sub_meshes,
+            mut dirty,
+            ..
+        } = buffers;
+        for (s, (_, sub_mesh)) in sub_meshes.iter().enumerate() {
+            // Sub-meshes of new keys were empty.
+            let old = old_lengths.get(s).copied().unwrap_or((0, 0));
+            dirty[s].finish(
+                old,
+                (sub_mesh.count_vertices(), indices_of(sub_mesh)?.len()),
+            );
+        }
+        Ok(dirty)
+    }
+}
+
+// The sub-meshes of a mesh that was split by material, while they are being updated, and the
+// parts of each of them that changed. Every sub-mesh keeps its quads the same way a full mesh
+// does, quads are removed by swapping the last quad of the sub-mesh into their place.
+struct SubMeshBuffers<'a> {
+    sub_meshes: &'a mut SubMeshes,
+    layout: &'a mut SubMeshLayout,
+    dirty: Vec<DirtyRanges>,
+}
+
+impl SubMeshBuffers<'_> {
+    // The sub-mesh the quad that starts at vertex `first` of the full mesh is in, and the first
+    // vertex of the quad in it.
+    fn quad(&self, first: usize) -> Result<(usize, usize), MeshemError> {
+        self.layout
+            .quads
+            .get(first / 4)
+            .map(|&(s, local)| (s as usize, local as usize))
+            .ok_or(MeshemError::VertexNotFound(first))
+    }
+
+    // The sub-mesh of the key, a new empty one is added at the end if there is none.
+    fn sub_mesh_of(&mut self, key: u32) -> usize {
+        if let Some(s) = self.layout.sub_meshes.iter().position(|k| *k == key) {
+            return s;
+        }
+        let mut sub_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        for attribute in self.layout.attributes.iter() {
+            sub_mesh.insert_attribute(*attribute, VertexAttributeValues::new(attribute.format));
+        }
+        sub_mesh.insert_indices(Indices::U32(vec![]));
+        self.sub_meshes.push((key, sub_mesh));
+        self.layout.sub_meshes.push(key);
+        self.layout.owners.push(vec![]);
+        self.dirty.push(DirtyRanges::default());
+        self.layout.sub_meshes.len() - 1
+    }
+}
+
+impl QuadBuffers for SubMeshBuffers<'_> {
+    fn count_vertices(&self) -> usize {
+        self.layout.quads.len() * 4
+    }
+
+    fn attributes(&self) -> Vec<MeshVertexAttribute> {
+        self.layout.attributes.clone()
+    }
+
+    fn quad_positions(&self, first: usize) -> Result<&[[f32; 3]], MeshemError> {
+        let (s, local) = self.quad(first)?;
+        positions_of(&self.sub_meshes[s].1)?
+            .get(local..local + 4)
+            .ok_or(MeshemError::VertexNotFound(first))
+    }
+
+    fn remove_quad(&mut self, first: usize, shift: bool) -> Result<(), MeshemError> {
+        let (s, local) = self.quad(first)?;
+        let sub_mesh = &mut self.sub_meshes[s].1;
+        // Make sure the indices are usable before changing anything.
+        indices_of_mut(sub_mesh)?;
+        for (_, vals) in sub_mesh.attributes_mut() {
+            for v in (local..local + 4).rev() {
+                vals.swap_remove(v);
+            }
+        }
+        let indices = indices_of_mut(sub_mesh)?;
+        for _ in 0..6 {
+            indices.pop();
+        }
+        self.dirty[s].mark_vertices(local..local + 4);
+
+        // The last quad of the sub-mesh was moved into the place of the quad.
+        let owners = &mut self.layout.owners[s];
+        if let Some(moved) = owners.pop() {
+            if local / 4 < owners.len() {
+                owners[local / 4] = moved;
+                self.layout.quads[moved as usize].1 = local as u32;
+            }
+        }
+
+        // Do the same to the quads of the full mesh.
+        let q = first / 4;
+        let quads = &mut self.layout.quads;
+        let moved = if shift {
+            quads.remove(q);
+            q..quads.len()
+        } else {
+            quads.swap_remove(q);
+            q..quads.len().min(q + 1)
+        };
+        for q in moved {
+            let (s, local) = quads[q];
+            self.layout.owners[s as usize][local as usize / 4] = q as u32;
+        }
+        Ok(())
+    }
+
+    fn push_quads(
+        &mut self,
+        voxel_index: usize,
+        vals: &[VertexAttributeValues],
+        indices: &[u32],
+    ) -> Result<(), MeshemError> {
+        let key = self.layout.keys.get(voxel_index).unwrap_or(0);
+        let s = self.sub_mesh_of(key);
+        let full_first = self.layout.quads.len() as u32 * 4;
+        let sub_mesh = &mut self.sub_meshes[s].1;
+        let local_first = sub_mesh.count_vertices() as u32;
+        // Make sure the indices and the attributes are usable before changing anything.
+        indices_of_mut(sub_mesh)?;
+        if let Some(missing) = self
+            .layout
+            .attributes
+            .iter()
+            .find(|attribute| sub_mesh.attribute(attribute.id).is_none())
+        {
+            return Err(MeshemError::MissingAttribute(missing.name));
+        }
+
+        for (attribute, new_vals) in self.layout.attributes.iter().zip(vals) {
+            sub_mesh
+                .attribute_mut(attribute.id)
+                .unwrap()
+                .extend(new_vals);
+        }
+        let sub_indices = indices_of_mut(sub_mesh)?;
+        self.dirty[s].mark_indices(sub_indices.len()..sub_indices.len() + indices.len());
+        sub_indices.extend(indices.iter().map(|v| v - full_first + local_first));
+        let local_end = sub_mesh.count_vertices() as u32;
+        self.dirty[s].mark_vertices(local_first as usize..local_end as usize);
+
+        // All the quads are of the same voxel, so they are in the same sub-mesh.
+        for (k, local) in (local_first..local_end).step_by(4).enumerate() {
+            self.layout.quads.push((s as u32, local));
+            self.layout.owners[s].push(full_first / 4 + k as u32);
+        }
+        Ok(())
+    }
+
+    fn overwrite_quad(
+        &mut self,
+        attribute: &MeshVertexAttribute,
+        first: usize,
+        vals: &VertexAttributeValues,
+    ) -> Result<(), MeshemError> {
+        let (s, local) = self.quad(first)?;
+        self.sub_meshes[s]
+            .1
+            .attribute_mut(attribute.id)
+            .ok_or(MeshemError::MissingAttribute(attribute.name))?
+            .overwrite(local, vals);
+        self.dirty[s].mark_vertices(local..local + 4);
+        Ok(())
+    }
+}
+
+/// Same as [`try_mesh_grid`], but the mesh is split into one mesh for each material key of the
+/// voxels (see `VoxelRegistry::get_material_key`), sorted by key. The metadata knows which
+/// sub-mesh each quad is in, update the sub-meshes with [`update_sub_meshes`].
+pub fn mesh_grid_by_material<T: Copy>(
+    dims: Dimensions,
+    outer_layer: &[Face],
+    grid: &[T],
+    reg: &impl VoxelRegistry<Voxel = T>,
+    meshing_algorithm: MeshingAlgorithm,
+    smooth_lighting_params: Option<SmoothLightingParameters>,
+) -> Result<(SubMeshes, MeshMD<T>), MeshemError> {
+    let (mesh, mut metadata) = try_mesh_grid(
+        dims,
+        outer_layer,
+        grid,
+        reg,
+        meshing_algorithm,
+        smooth_lighting_params,
+    )?;
+    let sub_meshes = metadata.split_by_material(&mesh, &FlatGrid::new(dims, grid), reg)?;
+    Ok((sub_meshes, metadata))
+}
+
+/// Same as [`try_update_mesh`], for meshes that were split by material (see
+/// [`mesh_grid_by_material`]). The quads are updated in place, in the sub-meshes they are in,
+/// and the parts of each sub-mesh that changed are returned, in the order of the sub-meshes.
+/// The sub-meshes keep their order, if the changes add voxels of a new key, its mesh is added at
+/// the end, and a mesh whose voxels are all gone stays (empty).
+/// Returns [`MeshemError::SubMeshMismatch`] without applying the changes if the sub-meshes aren't
+/// the ones the metadata knows of.
+pub fn update_sub_meshes<T: std::fmt::Debug>(
+    sub_meshes: &mut SubMeshes,
+    metadata: &mut MeshMD<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+) -> Result<Vec<DirtyRanges>, MeshemError> {
+    let mut layout = metadata
+        .sub_meshes
+        .take()
+        .ok_or(MeshemError::SubMeshMismatch)?;
+    let dirty = layout.update(sub_meshes, metadata, reg);
+    metadata.sub_meshes = Some(layout);
+    dirty
+}
+
+impl<T> MeshMD<T> {
+    /// Split `mesh` (generated with this metadata, by any of the meshing functions) into one
+    /// mesh for each material key of the voxels of `grid`, the grid it was generated from,
+    /// sorted by key. From now on the metadata knows which sub-mesh each quad is in (see
+    /// [`MeshMD::sub_mesh_quad`]).
+    pub fn split_by_material(
+        &mut self,
+        mesh: &Mesh,
+        grid: &impl VoxelGrid<T>,
+        reg: &impl VoxelRegistry<Voxel = T>,
+    ) -> Result<SubMeshes, MeshemError> {
+        let dims = grid.dims();
+        MeshemError::check_grid_size(dims, self.vivi.vivi.len())?;
+        let keys: Rle<u32> = (0..self.vivi.vivi.len())
+            .map(|index| reg.get_material_key(&grid.get(three_d_cords_arr(index, dims))))
+            .collect();
+        let mut sub_meshes: Vec<u32> = self
+            .vivi
+            .vivi
+            .iter()
+            .enumerate()
+            .filter(|(_, quads)| !quads.is_empty())
+            .filter_map(|(index, _)| keys.get(index))
+            .collect();
+        sub_meshes.sort_unstable();
+        sub_meshes.dedup();
+        self.sub_meshes = Some(SubMeshLayout {
+            keys,
+            sub_meshes,
+            quads: vec![],
+            owners: vec![],
+            attributes: mesh.attributes().map(|(attribute, _)| *attribute).collect(),
+        });
+        self.split_sub_meshes(mesh)
+    }
+
+    /// Split the full mesh into the sub-meshes again, after it was put back together with
+    /// [`MeshMD::join_sub_meshes`] (for example by `compact_sub_meshes`). The sub-meshes keep
+    /// their order, meshes of new keys are added at the end.
+    pub fn split_sub_meshes(&mut self, mesh: &Mesh) -> Result<SubMeshes, MeshemError> {
+        let layout = self
+            .sub_meshes
+            .as_mut()
+            .ok_or(MeshemError::SubMeshMismatch)?;
+        let quads_count = mesh.count_vertices() / 4;
+
+        layout.owners = vec![vec![]; layout.sub_meshes.len()];
+        layout.quads.clear();
+        for q in 0..quads_count as u32 {
+            let voxel = self
+                .vivi
+                .map
+                .get(&(q * 4))
+                .ok_or(MeshemError::VertexNotFound(q as usize * 4))?;
+            let key = layout
+                .keys
+                .get((voxel & OFFSET_CONST) as usize)
+                .unwrap_or(0);
+            let s = match layout.sub_meshes.iter().position(|k| *k == key) {
+                Some(s) => s,
+                None => {
+                    layout.sub_meshes.push(key);
+                    layout.owners.push(vec![]);
+                    layout.sub_meshes.len() - 1
+                }
+            };
+            layout
+                .quads
+                .push((s as u32, layout.owners[s].len() as u32 * 4));
+            layout.owners[s].push(q);
+        }
+
+        let mut indices: Vec<Vec<u32>> = vec![vec![]; layout.owners.len()];
+        for t in indices_of(mesh)?.chunks_exact(3) {
+            let q = t[0] / 4;
+            if t.iter().any(|v| v / 4 != q) {
+                continue;
+            }
+            let Some(&(s, first)) = layout.quads.get(q as usize) else {
+                return Err(MeshemError::VertexNotFound(t[0] as usize));
+            };
+            indices[s as usize].extend(t.iter().map(|v| v - q * 4 + first));
+        }
+
+        let mut sub_meshes = Vec::with_capacity(layout.owners.len());
+        for ((key, owners), indices) in layout.sub_meshes.iter().zip(&layout.owners).zip(indices) {
+            // The vertices of the full mesh the sub-mesh takes, in order.
+            let order: Vec<u32> = owners.iter().flat_map(|q| q * 4..q * 4 + 4).collect();
+            let mut sub_mesh = Mesh::new(
+                PrimitiveTopology::TriangleList,
+                RenderAssetUsages::default(),
+            );
+            for (attribute, vals) in mesh.attributes() {
+                sub_mesh.insert_attribute(*attribute, vals.get_needed(&order));
+            }
+            sub_mesh.insert_indices(Indices::U32(indices));
+            sub_meshes.push((*key, sub_mesh));
+        }
+        Ok(sub_meshes)
+    }
+
+    /// Put the sub-meshes the mesh was split into back together into the full mesh, for the
+    /// functions that need it (`MeshMD::verify`...). Split it again with
+    /// [`MeshMD::split_sub_meshes`].
+    pub fn join_sub_meshes(&self, sub_meshes: &[(u32, Mesh)]) -> Result<Mesh, MeshemError> {
+        let layout = self
+            .sub_meshes
+            .as_ref()
+            .ok_or(MeshemError::SubMeshMismatch)?;
+        layout.check(sub_meshes)?;
+
+        // The first vertex of each sub-mesh, if they were all put one after the other.
+        let mut offsets = Vec::with_capacity(sub_meshes.len());
+        let mut total = 0;
+        for (_, sub_mesh) in sub_meshes {
+            offsets.push(total);
+            total += sub_mesh.count_vertices() as u32;
+        }
+        let mut order: Vec<u32> = Vec::with_capacity(layout.quads.len() * 4);
+        for &(s, first) in layout.quads.iter() {
+            let start = offsets[s as usize] + first;
+            order.extend(start..start + 4);
+        }
+
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        for attribute in layout.attributes.iter() {
+            let mut vals = VertexAttributeValues::new(attribute.format);
+            for (_, sub_mesh) in sub_meshes {
+                let sub_vals = sub_mesh
+                    .attribute(attribute.id)
+                    .ok_or(MeshemError::MissingAttribute(attribute.name))?;
+                vals.extend(sub_vals);
+            }
+            mesh.insert_attribute(*attribute, vals.get_needed(&order));
+        }
+
+        // The triangles of each quad of the full mesh.
+        let mut triangles: Vec<Vec<u32>> = vec![vec![]; layout.quads.len()];
+        for ((_, sub_mesh), owners) in sub_meshes.iter().zip(layout.owners.iter()) {
+            for t in indices_of(sub_mesh)?.chunks_exact(3) {
+                let local = t[0] / 4;
+                let Some(q) = owners.get(local as usize) else {
+                    return Err(MeshemError::VertexNotFound(t[0] as usize));
+                };
+                triangles[*q as usize].extend(t.iter().map(|v| v - local * 4 + q * 4));
+            }
+        }
+        mesh.insert_indices(Indices::U32(triangles.concat()));
+        Ok(mesh)
+    }
+
+    /// The material key of the sub-mesh the quad of the voxel on its `face` side is in, and the
+    /// first vertex of the quad in that sub-mesh, as of the last time the mesh was split. None if
+    /// there is no such quad, or the mesh wasn't split by material.
+    pub fn sub_mesh_quad(&self, voxel_index: usize, face: Face) -> Option<(u32, usize)> {
+        let layout = self.sub_meshes.as_ref()?;
+        if voxel_index >= self.vivi.vivi.len() {
+            return None;
+        }
+        let quad = self.vivi.get_quad_index(face, voxel_index)?;
+        let (s, first) = layout.quads.get(quad as usize / 4)?;
+        Some((layout.sub_meshes[*s as usize], *first as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::prelude::*;
+
+    const DIMS: Dimensions = (4, 4, 4);
+
+    // The quads of the mesh, as the bits of their positions, sorted.
+    fn quads(mesh: &Mesh) -> Vec<[[u32; 3]; 4]> {
+        let mut r: Vec<[[u32; 3]; 4]> = positions_of(mesh)
+            .unwrap()
+            .chunks_exact(4)
+            .map(|q| std::array::from_fn(|v| q[v].map(f32::to_bits)))
+            .collect();
+        r.sort_unstable();
+        r
+    }
+
+    #[test]
+    fn updated_sub_meshes_match_fresh_ones() {
+        let reg = TestRegistry::new();
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            // No voxel 4, so the sub-mesh of its material is added by the updates.
+            let mut grid = random_grid(&mut rng, 64, 3);
+            let (mut sub_meshes, mut md) =
+                mesh_grid_by_material(DIMS, &[], &grid, &reg, MeshingAlgorithm::Culling, None)
+                    .unwrap();
+            assert_eq!(sub_meshes.len(), 1);
+
+            for _ in 0..2 {
+                for index in 0..64 {
+                    let (change, voxel, new) = match (grid[index], rng.gen_range(0..4)) {
+                        (_, 0) => continue,
+                        (0, _) => (VoxelChange::Added, 4, 4),
+                        (old, 1) => (VoxelChange::Broken, old, 0),
+                        // From 3 to 4 and from 4 to 1, the quads move to the other sub-mesh.
+                        (old, _) => (VoxelChange::Replaced { old }, old % 4 + 1, old % 4 + 1),
+                    };
+                    grid[index] = new;
+                    md.log(change, index, voxel, neighbors_of(&grid, DIMS, index));
+                }
+                update_sub_meshes(&mut sub_meshes, &mut md, &reg).unwrap();
+            }
+
+            // A sub-mesh whose voxels are all gone stays, empty.
+            let (fresh, _) =
+                mesh_grid_by_material(DIMS, &[], &grid, &reg, MeshingAlgorithm::Culling, None)
+                    .unwrap();
+            assert_eq!(sub_meshes.len(), 2);
+            for (key, sub_mesh) in sub_meshes.iter() {
+                let expected = fresh
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map_or(vec![], |(_, m)| quads(m));
+                assert_eq!(quads(sub_mesh), expected, "seed {}, sub-mesh {}", seed, key);
+            }
+            let joined = md.join_sub_meshes(&sub_meshes).unwrap();
+            let report = md.verify(&joined, &FlatGrid::new(DIMS, &grid), &reg);
+            assert!(report.is_ok(), "seed {}: {:?}", seed, report.issues);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Helpers shared by the unit tests: a small registry of textured cubes, random grids, and a way
+//! to compare meshes.
+use crate::prelude::*;
+use bevy::mesh::MeshVertexAttribute;
+use bevy::prelude::*;
+use rand::prelude::*;
+
+/// Voxel 0 is air, voxels 1 to 3 are cubes with different textures, and voxel 4 is a cube that
+/// only covers its bottom side, so the faces next to it aren't culled. Voxel 4 is drawn with
+/// material 1, the others with material 0.
+pub(crate) struct TestRegistry {
+    meshes: Vec<Mesh>,
+    pub(crate) reserved_quads: usize,
+}
+
+impl TestRegistry {
+    pub(crate) fn new() -> Self {
+        let cube = |t: u32| {
+            generate_voxel_mesh(
+                [1.0; 3],
+                [4, 4],
+                [
+                    (Top, [t, 0]),
+                    (Bottom, [t, 1]),
+                    (Right, [t, 2]),
+                    (Left, [t, 3]),
+                    (Back, [t, 0]),
+                    (Forward, [t, 1]),
+                ],
+                [0.0; 3],
+                0.0,
+                Some(0.8),
+                1.0,
+            )
+        };
+        TestRegistry {
+            meshes: (0..4).map(cube).collect(),
+            reserved_quads: 0,
+        }
+    }
+}
+
+impl VoxelRegistry for TestRegistry {
+    type Voxel = u16;
+
+    fn get_mesh(&self, voxel: &u16) -> VoxelMesh<&Mesh> {
+        match voxel {
+            0 => VoxelMesh::Null,
+            v => VoxelMesh::NormalCube(&self.meshes[*v as usize - 1]),
+        }
+    }
+
+    fn is_covering(&self, voxel: &u16, side: Face) -> bool {
+        match voxel {
+            0 => false,
+            4 => side == Bottom,
+            _ => true,
+        }
+    }
+
+    fn get_center(&self) -> [f32; 3] {
+        [0.0; 3]
+    }
+
+    fn get_voxel_dimensions(&self) -> [f32; 3] {
+        [1.0; 3]
+    }
+
+    fn all_attributes(&self) -> Vec<MeshVertexAttribute> {
+        vec![
+            Mesh::ATTRIBUTE_POSITION,
+            Mesh::ATTRIBUTE_UV_0,
+            Mesh::ATTRIBUTE_NORMAL,
+            Mesh::ATTRIBUTE_COLOR,
+        ]
+    }
+
+    fn get_material_key(&self, voxel: &u16) -> u32 {
+        (*voxel == 4) as u32
+    }
+
+    fn reserved_quads(&self) -> usize {
+        self.reserved_quads
+    }
+}
+
+/// A grid of random voxels (up to `max`), about a third of it air.
+pub(crate) fn random_grid(rng: &mut StdRng, len: usize, max: u16) -> Vec<u16> {
+    (0..len)
+        .map(|_| match rng.gen_bool(0.35) {
+            true => 0,
+            false => rng.gen_range(1..=max),
+        })
+        .collect()
+}
+
+/// The neighbors of the voxel at `index`, the way they are logged.
+pub(crate) fn neighbors_of(grid: &[u16], dims: Dimensions, index: usize) -> [Option<u16>; 6] {
+    FlatGrid::new(dims, grid)
+        .get_neighbors(three_d_cords_arr(index, dims))
+        .map(|v| v.filter(|v| *v != 0))
+}
+
+/// Assert that the meshes have the same values for every attribute and the same indices, in the
+/// same order.
+pub(crate) fn assert_same_mesh(mesh: &Mesh, expected: &Mesh, context: &str) {
+    assert_eq!(
+        mesh.attributes().count(),
+        expected.attributes().count(),
+        "{}",
+        context
+    );
+    for (attribute, vals) in expected.attributes() {
+        assert_eq!(
+            mesh.attribute(attribute.id),
+            Some(vals),
+            "{}: {}",
+            context,
+            attribute.name
+        );
+    }
+    assert_eq!(mesh.indices(), expected.indices(), "{}", context);
+}
@@ -0,0 +1,122 @@
+//! This module contains the texture array output mode, an alternative to atlas UVs. Each face
+//! carries the layer of a `texture_2d_array` it's textured with, and its UVs are local (0..1), so
+//! there is no bleeding between textures at smaller mip levels (and no need for padding).
+use crate::prelude::*;
+use bevy::asset::embedded_asset;
+use bevy::mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef};
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, SpecializedMeshPipelineError, VertexFormat,
+};
+use bevy::shader::ShaderRef;
+
+/// The layer of the texture array each vertex is textured with. Add it to
+/// `VoxelRegistry::all_attributes` when meshing voxels generated with
+/// [`generate_voxel_mesh_array`].
+pub const ATTRIBUTE_TEXTURE_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_TextureLayer", 988_540_917, VertexFormat::Uint32);
+
+const SHADER_PATH: &str = "embedded://bevy_meshem/texture_array.wgsl";
+
+/// Same as `generate_voxel_mesh`, but instead of a tile in an atlas, each face is given the
+/// layer of the texture array it's textured with (see [`ATTRIBUTE_TEXTURE_LAYER`]), and the UVs
+/// of each face go from 0 to 1.
+pub fn generate_voxel_mesh_array(
+    voxel_dims: [f32; 3],
+    layers: [(Face, u32); 6],
+    voxel_center: [f32; 3],
+    default_color_intensity: Option<f32>,
+    alpha: f32,
+) -> Mesh {
+    // An atlas of a single tile without padding has the same UVs as every layer of the array.
+    let mut cube_mesh = generate_voxel_mesh(
+        voxel_dims,
+        [1, 1],
+        layers.map(|(face, _)| (face, [0, 0])),
+        voxel_center,
+        0.0,
+        default_color_intensity,
+        alpha,
+    );
+    let mut face_layers = [0; 6];
+    for (face, layer) in layers {
+        face_layers[face as usize] = layer;
+    }
+    // The vertices are sorted by face, 4 vertices for each face.
+    cube_mesh.insert_attribute(
+        ATTRIBUTE_TEXTURE_LAYER,
+        (0..24).map(|v| face_layers[v / 4]).collect::<Vec<u32>>(),
+    );
+    cube_mesh
+}
+
+/// A material that textures meshes generated with [`generate_voxel_mesh_array`] from a texture
+/// array, using the layer of each vertex. It's unlit, the vertex colors (if the mesh has any)
+/// are multiplied into the texture, so Smooth Lighting still applies.
+/// To tile the textures across UVs bigger than 1, the sampler of the image needs to repeat.
+/// Add [`TextureArrayPlugin`] to use it.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct TextureArrayMaterial {
+    /// An image with `TextureViewDimension::D2Array`, for example an image loaded with
+    /// `ImageLoaderSettings::array_layout`.
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub array_texture: Handle<Image>,
+    pub alpha_mode: AlphaMode,
+}
+
+impl TextureArrayMaterial {
+    pub fn new(array_texture: Handle<Image>) -> Self {
+        TextureArrayMaterial {
+            array_texture,
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+}
+
+impl Material for TextureArrayMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let mut attributes = vec![
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            ATTRIBUTE_TEXTURE_LAYER.at_shader_location(2),
+        ];
+        if layout.0.contains(Mesh::ATTRIBUTE_COLOR) {
+            attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(3));
+            descriptor.vertex.shader_defs.push("VERTEX_COLORS".into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("VERTEX_COLORS".into());
+            }
+        }
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&attributes)?];
+        Ok(())
+    }
+}
+
+/// Embeds the shader of [`TextureArrayMaterial`], and registers the material.
+pub struct TextureArrayPlugin;
+
+impl Plugin for TextureArrayPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "texture_array.wgsl");
+        app.add_plugins(MaterialPlugin::<TextureArrayMaterial>::default());
+    }
+}
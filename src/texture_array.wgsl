@@ -0,0 +1,46 @@
+// The shader of `TextureArrayMaterial`. It samples the layer of the texture array given by the
+// `ATTRIBUTE_TEXTURE_LAYER` attribute of each vertex. It's unlit, the vertex colors (which hold
+// the Smooth Lighting, if it's applied) are multiplied into the texture.
+#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(0) var array_texture: texture_2d_array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(1) var array_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) layer: u32,
+#ifdef VERTEX_COLORS
+    @location(3) color: vec4<f32>,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(
+        get_world_from_local(vertex.instance_index),
+        vec4<f32>(vertex.position, 1.0),
+    );
+    out.uv = vertex.uv;
+    out.layer = vertex.layer;
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#else
+    out.color = vec4<f32>(1.0);
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(array_texture, array_sampler, in.uv, in.layer) * in.color;
+}
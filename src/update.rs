@@ -1,160 +1,443 @@
 // use crate::pbs::*;
 use crate::prelude::*;
-use bevy::mesh::{Indices, VertexAttributeValues};
+use bevy::mesh::{MeshVertexAttribute, VertexAttributeValues};
+use std::ops::Range;
 
-/// The function updates the mesh according to the change log in the mesh meta data.
+/// The parts of the buffers of a mesh that were changed by updating it, so they can be uploaded
+/// to the GPU without re-uploading the whole buffers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DirtyRanges {
+    /// The vertices (in all the attributes) that were changed or added, sorted, the ranges don't
+    /// overlap.
+    pub vertices: Vec<Range<usize>>,
+    /// The indices that were changed or added, sorted, the ranges don't overlap.
+    pub indices: Vec<Range<usize>>,
+    /// Whether the vertex or index buffer is longer than it was before updating, in which case the
+    /// GPU buffers need to grow too.
+    pub grew: bool,
+}
+
+impl DirtyRanges {
+    /// True if nothing was changed.
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.indices.is_empty()
+    }
+
+    pub(crate) fn mark_vertices(&mut self, range: Range<usize>) {
+        self.vertices.push(range);
+    }
+
+    pub(crate) fn mark_indices(&mut self, range: Range<usize>) {
+        self.indices.push(range);
+    }
+
+    // Sort and merge the ranges, and drop the parts that are past the end of the buffers.
+    pub(crate) fn finish(&mut self, old_lengths: (usize, usize), new_lengths: (usize, usize)) {
+        fn normalize(ranges: &mut Vec<Range<usize>>, len: usize) {
+            let mut sorted: Vec<Range<usize>> = ranges
+                .drain(..)
+                .map(|r| r.start..r.end.min(len))
+                .filter(|r| !r.is_empty())
+                .collect();
+            sorted.sort_unstable_by_key(|r| r.start);
+            for r in sorted {
+                match ranges.last_mut() {
+                    Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                    _ => ranges.push(r),
+                }
+            }
+        }
+        normalize(&mut self.vertices, new_lengths.0);
+        normalize(&mut self.indices, new_lengths.1);
+        self.grew = new_lengths.0 > old_lengths.0 || new_lengths.1 > old_lengths.1;
+    }
+}
+
+/// The function updates the mesh according to the change log in the mesh meta data, and returns
+/// the parts of the mesh that changed.
+/// Panics if the mesh couldn't be updated, see [`try_update_mesh`].
 pub fn update_mesh<T: std::fmt::Debug>(
     mesh: &mut Mesh,
     metadata: &mut MeshMD<T>,
     reg: &impl VoxelRegistry<Voxel = T>,
-) {
+) -> DirtyRanges {
+    match try_update_mesh(mesh, metadata, reg) {
+        Ok(dirty) => dirty,
+        Err(e) => panic!("Couldn't update the mesh: {}", e),
+    }
+}
+
+/// Same as [`update_mesh`], but returns an error instead of panicking. The change log is cleared
+/// either way, if an error is returned, the mesh may have been partially updated, and it should
+/// be regenerated.
+pub fn try_update_mesh<T: std::fmt::Debug>(
+    mesh: &mut Mesh,
+    metadata: &mut MeshMD<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+) -> Result<DirtyRanges, MeshemError> {
+    let old_lengths = (mesh.count_vertices(), indices_of(mesh)?.len());
+    if let Some(sub_meshes) = metadata.sub_meshes.as_mut() {
+        sub_meshes.log_keys(reg, &metadata.changed_voxels, metadata.dims);
+    }
+    let mut buffers = MeshBuffers {
+        mesh,
+        dirty: DirtyRanges::default(),
+    };
+    apply_logged_changes(&mut buffers, metadata, reg)?;
+    let MeshBuffers { mesh, mut dirty } = buffers;
+    dirty.finish(
+        old_lengths,
+        (mesh.count_vertices(), indices_of(mesh)?.len()),
+    );
+    Ok(dirty)
+}
+
+// Apply the logged changes to the buffers of the mesh, and save them in the history.
+pub(crate) fn apply_logged_changes<T: std::fmt::Debug>(
+    mesh: &mut impl QuadBuffers,
+    metadata: &mut MeshMD<T>,
+    reg: &impl VoxelRegistry<Voxel = T>,
+) -> Result<(), MeshemError> {
     let mut min = usize::MAX;
     let mut max = usize::MIN;
-    let voxel_dims = reg.get_voxel_dimensions();
-    for (voxel, index, change, neighbors) in metadata.changed_voxels.iter() {
+    let changed_voxels = std::mem::take(&mut metadata.changed_voxels);
+    let mut oriented_meshes = OrientedMeshes::default();
+    for (voxel, index, change, neighbors) in changed_voxels.iter() {
+        if *index >= metadata.vivi.vivi.len() {
+            return Err(MeshemError::VoxelIndexOutOfBounds {
+                index: *index,
+                len: metadata.vivi.vivi.len(),
+            });
+        }
         if *index < min {
             min = *index;
         }
         if *index > max {
             max = *index;
         }
-        let temp = three_d_cords(*index, metadata.dims);
-        let position_offset = (
-            temp.0 as f32 * voxel_dims[0],
-            temp.1 as f32 * voxel_dims[1],
-            temp.2 as f32 * voxel_dims[2],
-        );
-        let neig: Neighbors = match change {
-            VoxelChange::AddFaces => neighbors
-                .iter()
-                .map(|x| x.is_some())
-                .collect::<Vec<bool>>()
-                .try_into()
-                .unwrap(),
-            _ => {
-                let mut n = [false; 6];
-                for (i, j) in neighbors.iter().enumerate() {
-                    match j {
-                        None => n[i] = true,
-                        Some(t) if !reg.is_covering(&t, Face::from(i).opposite()) => n[i] = true,
-                        Some(_) => {}
-                    }
-                }
-                n
-            }
-        };
+        apply_change(
+            mesh,
+            metadata,
+            reg,
+            &mut oriented_meshes,
+            voxel,
+            *index,
+            change,
+            neighbors,
+        )?;
+    }
 
-        let covering: Neighbors = {
-            let mut n = [false; 6];
-            for i in 0..6 {
-                n[i] = reg.is_covering(voxel, Face::from(i));
-            }
-            n
-        };
+    // if metadata.pbs.is_some() {
+    //     apply_pbs(
+    //         mesh,
+    //         &metadata.vivi,
+    //         metadata.dims,
+    //         min.checked_sub(metadata.dims.0 * metadata.dims.2 * 2)
+    //             .unwrap_or(0),
+    //         max.checked_add(metadata.dims.0 * metadata.dims.2 * 2)
+    //             .unwrap_or(usize::MAX),
+    //         metadata.pbs.unwrap(),
+    //         reg.get_voxel_dimensions(),
+    //     );
+    // }
+
+    metadata.record_history(changed_voxels);
+    Ok(())
+}
 
-        let neighboring_voxels: Vec<(Face, &Mesh)> = {
-            let mut r: Vec<(Face, &Mesh)> = vec![];
+// Apply one logged change to the mesh, the rotated voxel meshes are kept in `oriented_meshes`
+// for the next changes.
+#[allow(clippy::too_many_arguments)]
+fn apply_change<T: std::fmt::Debug, R: VoxelRegistry<Voxel = T>>(
+    mesh: &mut impl QuadBuffers,
+    metadata: &mut MeshMD<T>,
+    reg: &R,
+    oriented_meshes: &mut OrientedMeshes<R>,
+    voxel: &T,
+    index: usize,
+    change: &VoxelChange<T>,
+    neighbors: &[Option<T>; 6],
+) -> Result<(), MeshemError> {
+    let voxel_dims = reg.get_voxel_dimensions();
+    let cords = three_d_cords_arr(index, metadata.dims);
+    let position_offset = (
+        cords[0] as f32 * voxel_dims[0],
+        cords[1] as f32 * voxel_dims[1],
+        cords[2] as f32 * voxel_dims[2],
+    );
+    let neig: Neighbors = match change {
+        VoxelChange::AddFaces => neighbors
+            .iter()
+            .map(|x| x.is_some())
+            .collect::<Vec<bool>>()
+            .try_into()
+            .unwrap(),
+        _ => {
+            let mut n = [false; 6];
             for (i, j) in neighbors.iter().enumerate() {
                 match j {
-                    None => continue,
-                    Some(t)
-                        if /* reg.is_covering(&t, Face::from(i).opposite()) && */
-                            reg.is_covering(voxel, Face::from(i)) =>
-                    {
-                        if let VoxelMesh::NormalCube(mesh) = reg.get_mesh(&t) {
-                            r.push((
-                                Face::from(i),
-                                mesh,
-                            ));
-                        }
+                    None => n[i] = true,
+                    Some(t) if !is_covering_oriented(reg, t, Face::from(i).opposite()) => {
+                        n[i] = true
                     }
-                    _ => continue,
+                    Some(_) => {}
                 }
             }
-            r
-        };
+            n
+        }
+    };
 
-        match *change {
-            VoxelChange::Added => {
-                if let VoxelMesh::NormalCube(voxel_mesh) = reg.get_mesh(voxel) {
-                    remove_voxel(mesh, &mut metadata.vivi, *index, [true; 6]);
-                    add_voxel_after_gen(
-                        neig,
-                        mesh,
-                        voxel_mesh,
-                        &mut metadata.vivi,
-                        *index,
-                        reg.get_center(),
-                        position_offset,
-                    );
-                    remove_quads_facing(mesh, &mut metadata.vivi, *index, metadata.dims, covering);
+    let covering: Neighbors = {
+        let mut n = [false; 6];
+        for i in 0..6 {
+            n[i] = is_covering_oriented(reg, voxel, Face::from(i));
+        }
+        n
+    };
+
+    for (i, j) in neighbors.iter().enumerate() {
+        if let Some(t) = j {
+            if is_covering_oriented(reg, voxel, Face::from(i)) {
+                oriented_meshes.insert(reg, t)?;
+            }
+        }
+    }
+    let neighboring_voxels: Vec<NeighborMesh> = {
+        let mut r: Vec<NeighborMesh> = vec![];
+        for (i, j) in neighbors.iter().enumerate() {
+            match j {
+                None => continue,
+                Some(t)
+                    if /* reg.is_covering(&t, Face::from(i).opposite()) && */
+                        is_covering_oriented(reg, voxel, Face::from(i)) =>
+                {
+                    if let VoxelMesh::NormalCube(mesh) = oriented_meshes.voxel_mesh(reg, t) {
+                        let tint = get_neighbor(index, Face::from(i), metadata.dims)
+                            .and_then(|n| reg.get_tint(t, three_d_cords_arr(n, metadata.dims)));
+                        r.push((
+                            Face::from(i),
+                            mesh,
+                            reg.randomize_uv_rotation(&t),
+                            tint,
+                        ));
+                    }
                 }
+                _ => continue,
             }
-            VoxelChange::Broken => {
-                remove_voxel(mesh, &mut metadata.vivi, *index, [true; 6]);
-                add_quads_facing(
+        }
+        r
+    };
+
+    match change {
+        VoxelChange::Added => {
+            oriented_meshes.insert(reg, voxel)?;
+            if let VoxelMesh::NormalCube(voxel_mesh) = oriented_meshes.voxel_mesh(reg, voxel) {
+                remove_voxel(mesh, &mut metadata.vivi, index, [true; 6], true)?;
+                add_voxel_after_gen(
+                    neig,
                     mesh,
+                    voxel_mesh,
                     &mut metadata.vivi,
-                    *index,
-                    neighboring_voxels,
+                    index,
                     reg.get_center(),
-                    reg.get_voxel_dimensions(),
-                    metadata.dims,
-                );
+                    position_offset,
+                    random_uv_cords(reg.randomize_uv_rotation(voxel), voxel_mesh, cords),
+                    reg.get_tint(voxel, cords),
+                )?;
+                remove_quads_facing(mesh, &mut metadata.vivi, index, metadata.dims, covering)?;
+            }
+        }
+        VoxelChange::Broken => {
+            remove_voxel(mesh, &mut metadata.vivi, index, [true; 6], true)?;
+            add_quads_facing(
+                mesh,
+                &mut metadata.vivi,
+                index,
+                neighboring_voxels,
+                reg.get_center(),
+                reg.get_voxel_dimensions(),
+                metadata.dims,
+            )?;
+        }
+        VoxelChange::Replaced { old } => {
+            if !replace_voxel_attributes(mesh, metadata, reg, oriented_meshes, old, voxel, index)? {
+                apply_change(
+                    mesh,
+                    metadata,
+                    reg,
+                    oriented_meshes,
+                    old,
+                    index,
+                    &VoxelChange::Broken,
+                    neighbors,
+                )?;
+                apply_change(
+                    mesh,
+                    metadata,
+                    reg,
+                    oriented_meshes,
+                    voxel,
+                    index,
+                    &VoxelChange::Added,
+                    neighbors,
+                )?;
             }
-            VoxelChange::CullFaces => {
-                remove_voxel(
+        }
+        VoxelChange::CullFaces => {
+            remove_voxel(
+                mesh,
+                &mut metadata.vivi,
+                index,
+                neighbors
+                    .iter()
+                    .map(|x| x.is_some())
+                    .collect::<Vec<bool>>()
+                    .try_into()
+                    .unwrap(),
+                false,
+            )?;
+        }
+        VoxelChange::AddFaces => {
+            oriented_meshes.insert(reg, voxel)?;
+            if let VoxelMesh::NormalCube(voxel_mesh) = oriented_meshes.voxel_mesh(reg, voxel) {
+                add_voxel_after_gen(
+                    neig,
                     mesh,
+                    voxel_mesh,
                     &mut metadata.vivi,
-                    *index,
-                    neighbors
-                        .iter()
-                        .map(|x| x.is_some())
-                        .collect::<Vec<bool>>()
-                        .try_into()
-                        .unwrap(),
-                );
+                    index,
+                    reg.get_center(),
+                    position_offset,
+                    random_uv_cords(reg.randomize_uv_rotation(voxel), voxel_mesh, cords),
+                    reg.get_tint(voxel, cords),
+                )?;
             }
-            VoxelChange::AddFaces => {
-                if let VoxelMesh::NormalCube(voxel_mesh) = reg.get_mesh(voxel) {
-                    add_voxel_after_gen(
-                        neig,
-                        mesh,
-                        voxel_mesh,
-                        &mut metadata.vivi,
-                        *index,
-                        reg.get_center(),
-                        position_offset,
-                    );
-                }
+        }
+    }
+    Ok(())
+}
+
+// Rewrite the attributes of the quads of a voxel that was replaced by `new` in place, other than
+// the positions (and the colors, if Smooth Lighting is applied). Only possible if both voxels are
+// normal cubes (that aren't shaped) that cover the same sides and have the same material key (so
+// their quads are in the same sub-mesh), and every quad of the voxel matches a quad of the new
+// voxel mesh vertex by vertex, otherwise returns false without changing anything.
+fn replace_voxel_attributes<T, R: VoxelRegistry<Voxel = T>>(
+    mesh: &mut impl QuadBuffers,
+    metadata: &MeshMD<T>,
+    reg: &R,
+    oriented_meshes: &mut OrientedMeshes<R>,
+    old: &T,
+    new: &T,
+    index: usize,
+) -> Result<bool, MeshemError> {
+    oriented_meshes.insert(reg, old)?;
+    oriented_meshes.insert(reg, new)?;
+    let (VoxelMesh::NormalCube(old_mesh), VoxelMesh::NormalCube(new_mesh)) = (
+        oriented_meshes.voxel_mesh(reg, old),
+        oriented_meshes.voxel_mesh(reg, new),
+    ) else {
+        return Ok(false);
+    };
+    if [&old_mesh, &new_mesh]
+        .iter()
+        .any(|m| m.attribute(ATTRIBUTE_FACE_TAG).is_some())
+    {
+        return Ok(false);
+    }
+    if (0..6).any(|i| {
+        let side = Face::from(i);
+        is_covering_oriented(reg, old, side) != is_covering_oriented(reg, new, side)
+    }) || reg.get_material_key(old) != reg.get_material_key(new)
+    {
+        return Ok(false);
+    }
+
+    let voxel_dims = reg.get_voxel_dimensions();
+    let temp = three_d_cords(index, metadata.dims);
+    let position_offset = [
+        temp.0 as f32 * voxel_dims[0],
+        temp.1 as f32 * voxel_dims[1],
+        temp.2 as f32 * voxel_dims[2],
+    ];
+    let new_quads = select_cube_quads([true; 6], false, new_mesh, reg.get_center(), 0)?;
+    let new_positions = positions_of(new_mesh)?;
+
+    // The first vertex of each quad of the voxel, and the vertices of the new voxel mesh to copy
+    // into them, each 4 vertices are one quad.
+    let mut quads: Vec<(Face, usize)> = vec![];
+    let mut needed: Vec<u32> = vec![];
+    for &q in metadata.vivi.vivi[index].iter() {
+        let face = face_from_u32(q & REVERSE_OFFSET_CONST);
+        let first = (q & OFFSET_CONST) as usize;
+        let Some(candidates) = new_quads
+            .quads
+            .iter()
+            .position(|(f, _)| *f == face)
+            .and_then(|k| new_quads.vertices.get(k * 4..k * 4 + 4))
+        else {
+            return Ok(false);
+        };
+        // The vertices have to match in order, the quad of a rotated voxel can start from
+        // another corner (and be split along the other diagonal).
+        for (p, &c) in mesh.quad_positions(first)?.iter().zip(candidates) {
+            let n = new_positions[c as usize];
+            if (0..3).any(|i| n[i] + position_offset[i] != p[i]) {
+                return Ok(false);
             }
+            needed.push(c);
         }
+        quads.push((face, first));
     }
+    let random_uvs = reg.randomize_uv_rotation(new);
+    let tint = reg.get_tint(new, three_d_cords_arr(index, metadata.dims));
 
-    metadata.changed_voxels.clear();
-    // if metadata.pbs.is_some() {
-    //     apply_pbs(
-    //         mesh,
-    //         &metadata.vivi,
-    //         metadata.dims,
-    //         min.checked_sub(metadata.dims.0 * metadata.dims.2 * 2)
-    //             .unwrap_or(0),
-    //         max.checked_add(metadata.dims.0 * metadata.dims.2 * 2)
-    //             .unwrap_or(usize::MAX),
-    //         metadata.pbs.unwrap(),
-    //         reg.get_voxel_dimensions(),
-    //     );
-    // }
+    let skip_color = metadata.smooth_lighting_params.is_some();
+    let mut new_vals = vec![];
+    for attribute in mesh.attributes() {
+        if attribute.id == Mesh::ATTRIBUTE_POSITION.id
+            || (skip_color && attribute.id == Mesh::ATTRIBUTE_COLOR.id)
+        {
+            continue;
+        }
+        let mut vals = needed_voxel_attribute(new_mesh, &attribute, &needed, (0.0, 0.0, 0.0))?;
+        if let (Some(tint), true) = (tint, attribute.id == Mesh::ATTRIBUTE_COLOR.id) {
+            tint_colors(&mut vals, tint);
+        }
+        new_vals.push((attribute, vals));
+    }
+    for (attribute, vals) in new_vals {
+        let id = attribute.id;
+        for (j, &(face, first)) in quads.iter().enumerate() {
+            let mut quad_vals = vals.get_needed(&(j as u32 * 4..j as u32 * 4 + 4).collect());
+            if random_uvs {
+                let steps = uv_rotation_hash(three_d_cords_arr(index, metadata.dims), face);
+                match &mut quad_vals {
+                    VertexAttributeValues::Float32x2(uvs) if id == Mesh::ATTRIBUTE_UV_0.id => {
+                        rotate_quad_uvs(uvs, [0, 1, 3, 2], steps)
+                    }
+                    VertexAttributeValues::Float32x4(tangents)
+                        if id == Mesh::ATTRIBUTE_TANGENT.id =>
+                    {
+                        rotate_quad_tangents(tangents, [0, 1, 3, 2], face, steps)
+                    }
+                    _ => {}
+                }
+            }
+            mesh.overwrite_quad(&attribute, first, &quad_vals)?;
+        }
+    }
+    Ok(true)
 }
 
 // The function removes all quads facing a voxel.
 fn remove_quads_facing(
-    mesh: &mut Mesh,
+    mesh: &mut impl QuadBuffers,
     vivi: &mut VIVI,
     voxel_index: usize,
     dims: Dimensions,
     covering: Neighbors,
-) {
+) -> Result<(), MeshemError> {
     let mut quad_to_remove: Neighbors;
     for i in 0..6 {
         let face = Face::from(i as usize);
@@ -164,69 +447,79 @@ fn remove_quads_facing(
         };
         quad_to_remove = [false; 6];
         quad_to_remove[face.opposite() as usize] = true;
-        if covering[face.opposite() as usize] {
-            remove_voxel(mesh, vivi, n, quad_to_remove);
+        if covering[face as usize] {
+            remove_voxel(mesh, vivi, n, quad_to_remove, false)?;
         }
     }
+    Ok(())
 }
 
-/// Function removes voxel from the big mesh.
-fn remove_voxel(mesh: &mut Mesh, vivi: &mut VIVI, voxel_index: usize, neig: Neighbors) {
+/// Function removes voxel from the big mesh, all of its quads on the sides in `neig`, and its
+/// interior quads if `interior`.
+fn remove_voxel(
+    mesh: &mut impl QuadBuffers,
+    vivi: &mut VIVI,
+    voxel_index: usize,
+    neig: Neighbors,
+    interior: bool,
+) -> Result<(), MeshemError> {
     for (i, b) in neig.iter().enumerate() {
         if !b {
             continue;
         }
         let face = Face::from(i);
-        let quad = match vivi.get_quad_index(face, voxel_index) {
-            None => continue,
-            Some(i) => i,
-        } as usize;
-        if quad + 25 >= mesh.count_vertices() {
-            for (_, vals) in mesh.attributes_mut() {
-                vals.remove(quad + 3);
-                vals.remove(quad + 2);
-                vals.remove(quad + 1);
-                vals.remove(quad + 0);
-            }
-            vivi.remove_quad(quad);
-            let mut tmp = quad;
-            while tmp != mesh.count_vertices() {
-                vivi.change_quad_index(tmp + 4, tmp);
-                tmp += 4;
-            }
-        } else {
-            for (_, vals) in mesh.attributes_mut() {
-                vals.swap_remove(quad + 3);
-                vals.swap_remove(quad + 2);
-                vals.swap_remove(quad + 1);
-                vals.swap_remove(quad + 0);
-            }
-            let ver_count = mesh.count_vertices();
-            vivi.remove_quad(quad);
-            vivi.change_quad_index(ver_count, quad);
+        // Shaped voxels can have more than one quad on a side.
+        while let Some(quad) = vivi.get_quad_index(face, voxel_index) {
+            remove_quad(mesh, vivi, quad as usize)?;
+        }
+    }
+    if interior {
+        while let Some(quad) = vivi.get_interior_quad_index(voxel_index) {
+            remove_quad(mesh, vivi, quad as usize)?;
         }
+    }
+    Ok(())
+}
 
-        let Indices::U32(indices) = mesh.indices_mut().expect("couldn't get indices data") else {
-            panic!("Expected U32 indices format");
-        };
-        for _ in 0..6 {
-            indices.pop();
+// Remove the quad that starts at vertex `quad` from the big mesh.
+fn remove_quad(
+    mesh: &mut impl QuadBuffers,
+    vivi: &mut VIVI,
+    quad: usize,
+) -> Result<(), MeshemError> {
+    if quad + 25 >= mesh.count_vertices() {
+        mesh.remove_quad(quad, true)?;
+        vivi.remove_quad(quad)?;
+        let mut tmp = quad;
+        while tmp != mesh.count_vertices() {
+            vivi.change_quad_index(tmp + 4, tmp)?;
+            tmp += 4;
         }
+    } else {
+        mesh.remove_quad(quad, false)?;
+        let ver_count = mesh.count_vertices();
+        vivi.remove_quad(quad)?;
+        vivi.change_quad_index(ver_count, quad)?;
     }
+    Ok(())
 }
 
+// A neighbor of a voxel, the side it's on, its mesh, whether its textures are rotated randomly,
+// and its tint.
+pub(crate) type NeighborMesh<'a> = (Face, &'a Mesh, bool, Option<[f32; 4]>);
+
 /// Function adds quads facing voxel.
-pub(crate) fn add_quads_facing(
-    mesh: &mut Mesh,
+fn add_quads_facing(
+    mesh: &mut impl QuadBuffers,
     vivi: &mut VIVI,
     voxel_index: usize,
-    neighboring_voxels: Vec<(Face, &Mesh)>,
+    neighboring_voxels: Vec<NeighborMesh>,
     center: [f32; 3],
     voxel_dims: [f32; 3],
     dims: Dimensions,
-) {
+) -> Result<(), MeshemError> {
     let mut neig: Neighbors;
-    for &(face, vmesh) in neighboring_voxels.iter() {
+    for (face, vmesh, randomize_uvs, tint) in neighboring_voxels {
         neig = [false; 6];
         neig[face.opposite() as usize] = true;
         let i = match get_neighbor(voxel_index, face, dims) {
@@ -239,20 +532,34 @@ pub(crate) fn add_quads_facing(
             temp.1 as f32 * voxel_dims[1],
             temp.2 as f32 * voxel_dims[2],
         );
-        add_voxel_after_gen(neig, mesh, vmesh, vivi, i, center, position_offset)
+        let random_uvs = random_uv_cords(randomize_uvs, vmesh, [temp.0, temp.1, temp.2]);
+        add_voxel_after_gen(
+            neig,
+            mesh,
+            vmesh,
+            vivi,
+            i,
+            center,
+            position_offset,
+            random_uvs,
+            tint,
+        )?;
     }
+    Ok(())
 }
 
 /// Function adds a voxel after the big mesh has already been generated.
 fn add_voxel_after_gen(
     neig: Neighbors,
-    main_mesh: &mut Mesh,
+    main_mesh: &mut impl QuadBuffers,
     voxel: &Mesh,
     vivi: &mut VIVI,
     voxel_index: usize,
     center: [f32; 3],
     position_offset: (f32, f32, f32),
-) {
+    random_uvs: Option<[usize; 3]>,
+    tint: Option<[f32; 4]>,
+) -> Result<(), MeshemError> {
     // Make sure we are not adding quads that already exist
     let mut neig = neig;
     for (i, b) in neig.iter_mut().enumerate() {
@@ -261,133 +568,216 @@ fn add_voxel_after_gen(
             *b = false;
         }
     }
+    // The interior quads are added with the voxel, and stay until it's removed.
+    let interior = vivi.get_interior_quad_index(voxel_index).is_none();
     let vertices_count = main_mesh.count_vertices();
-    let Indices::U32(ref mut indices_main) =
-        main_mesh.indices_mut().expect("Couldn't get indices data")
-    else {
-        panic!("Indices format should be U32");
-    };
+    let quads = select_cube_quads(neig, interior, voxel, center, vertices_count as u32)?;
 
-    let pos_attribute = voxel
-        .attribute(Mesh::ATTRIBUTE_POSITION)
-        .expect("couldn't get voxel mesh data");
-    let VertexAttributeValues::Float32x3(positions) = pos_attribute else {
-        panic!("Unexpected vertex format for position attribute, expected Float32x3.");
-    };
-    let Indices::U32(indices) = voxel.indices().expect("couldn't get indices data") else {
-        panic!("Expected U32 indices format");
-    };
-    let triangles = indices
-        .chunks(3)
-        .map(|chunk| (chunk[0], chunk[1], chunk[2]));
-
-    // define the indices and vertices we want to save of the voxel mesh
-    let mut indices_to_save: Vec<u32> = vec![];
-    // helper data structure
-    let mut vertices_to_save: Vec<(bool, u32, Face)> = vec![(false, 0, Face::Top); positions.len()];
-    // sorted vertices by the quad they are in
-    let mut sorted_vertices: Vec<Option<Vec<u32>>> = vec![None; 6];
-    // the final array of the vertices, it will be sorted, each 4 vertices will be a
-    // part of one quad, we sort them this way to efficiently update the vivi.
-    let mut final_vertices: Vec<u32> = vec![];
-
-    // iterate over all the triangles in the mesh
-    for (a, b, c) in triangles {
-        let v1 = positions[a as usize];
-        let v2 = positions[b as usize];
-        let v3 = positions[c as usize];
-        let mut save = (false, Top);
-
-        // see which side of the voxel the triangle belongs to
-        for i in 0..3 {
-            if v1[i] == v2[i] && v2[i] == v3[i] && v1[i] == v3[i] {
-                match (i, center[i] > v1[i]) {
-                    (0, true) if neig[3] => save = (true, Left),
-                    (0, false) if neig[2] => save = (true, Right),
-                    (1, true) if neig[1] => save = (true, Bottom),
-                    (1, false) if neig[0] => save = (true, Top),
-                    (2, true) if neig[5] => save = (true, Forward),
-                    (2, false) if neig[4] => save = (true, Back),
-                    _ => save = (false, Top),
-                }
-                break;
-            }
+    // Collect all the attributes before changing anything, so an error doesn't leave a
+    // half-added voxel behind.
+    let mut atts = vec![];
+    for attr in main_mesh.attributes() {
+        let mut att = needed_voxel_attribute(voxel, &attr, &quads.vertices, position_offset)?;
+        if let Some(cords) = random_uvs {
+            rotate_uvs_randomly(&mut att, attr.id, &quads, vertices_count as u32, cords);
+        }
+        if let (Some(tint), true) = (tint, attr.id == Mesh::ATTRIBUTE_COLOR.id) {
+            tint_colors(&mut att, tint);
         }
+        atts.push(att);
+    }
+    main_mesh.push_quads(voxel_index, &atts, &quads.indices)?;
+    for (face, vertex) in quads.quads {
+        vivi.insert(face, voxel_index, vertex);
+    }
+    for (face, vertex) in quads.interior {
+        vivi.insert_interior(face, voxel_index, vertex);
+    }
+    Ok(())
+}
 
-        // save the vertices
-        if save.0 {
-            let quad: usize = save.1.into();
-            indices_to_save.push(a);
-            indices_to_save.push(b);
-            indices_to_save.push(c);
-            match sorted_vertices[quad] {
-                None => {
-                    sorted_vertices[quad] = Some(vec![a, b, c]);
-                    vertices_to_save[a as usize].0 = true;
-                    vertices_to_save[b as usize].0 = true;
-                    vertices_to_save[c as usize].0 = true;
-                    vertices_to_save[a as usize].1 = 0;
-                    vertices_to_save[b as usize].1 = 1;
-                    vertices_to_save[c as usize].1 = 2;
-                    vertices_to_save[a as usize].2 = save.1;
-                    vertices_to_save[b as usize].2 = save.1;
-                    vertices_to_save[c as usize].2 = save.1;
-                }
-                Some(ref mut v) => {
-                    for &i in [a, b, c].iter() {
-                        if !vertices_to_save[i as usize].0 {
-                            v.push(i);
-                            vertices_to_save[i as usize].2 = save.1;
-                            vertices_to_save[i as usize].1 = v.len() as u32 - 1;
-                            vertices_to_save[i as usize].0 = true;
-                        }
-                    }
+// The buffers the quads of a mesh are in, while it's being updated. The vertices are numbered as
+// if all the quads were in one mesh, the way the vivi sees them, even if they are split into
+// sub-meshes (see `update_sub_meshes`).
+pub(crate) trait QuadBuffers {
+    fn count_vertices(&self) -> usize;
+
+    // The attributes of the mesh, the values of new quads are given in this order.
+    fn attributes(&self) -> Vec<MeshVertexAttribute>;
+
+    // The positions of the 4 vertices of the quad that starts at vertex `first`.
+    fn quad_positions(&self, first: usize) -> Result<&[[f32; 3]], MeshemError>;
+
+    // Remove the quad that starts at vertex `first`, and the last 6 indices. The last quad takes
+    // its place, or if `shift`, all the quads after it move back by one quad.
+    fn remove_quad(&mut self, first: usize, shift: bool) -> Result<(), MeshemError>;
+
+    // Add quads of the voxel at the end of the mesh, `vals` has the values of every attribute,
+    // and `indices` are the triangles of the quads, as vertices of the mesh.
+    fn push_quads(
+        &mut self,
+        voxel_index: usize,
+        vals: &[VertexAttributeValues],
+        indices: &[u32],
+    ) -> Result<(), MeshemError>;
+
+    // Overwrite the values of an attribute of the quad that starts at vertex `first`.
+    fn overwrite_quad(
+        &mut self,
+        attribute: &MeshVertexAttribute,
+        first: usize,
+        vals: &VertexAttributeValues,
+    ) -> Result<(), MeshemError>;
+}
+
+// A mesh that is being updated, and the parts of it that changed.
+struct MeshBuffers<'a> {
+    mesh: &'a mut Mesh,
+    dirty: DirtyRanges,
+}
+
+impl QuadBuffers for MeshBuffers<'_> {
+    fn count_vertices(&self) -> usize {
+        self.mesh.count_vertices()
+    }
+
+    fn attributes(&self) -> Vec<MeshVertexAttribute> {
+        self.mesh
+            .attributes()
+            .map(|(attribute, _)| *attribute)
+            .collect()
+    }
+
+    fn quad_positions(&self, first: usize) -> Result<&[[f32; 3]], MeshemError> {
+        positions_of(self.mesh)?
+            .get(first..first + 4)
+            .ok_or(MeshemError::VertexNotFound(first))
+    }
+
+    fn remove_quad(&mut self, first: usize, shift: bool) -> Result<(), MeshemError> {
+        // Make sure the indices are usable before changing anything.
+        indices_of_mut(self.mesh)?;
+        for (_, vals) in self.mesh.attributes_mut() {
+            for v in (first..first + 4).rev() {
+                if shift {
+                    vals.remove(v);
+                } else {
+                    vals.swap_remove(v);
                 }
             }
         }
+        if shift {
+            // All the vertices after the quad were shifted.
+            self.dirty.mark_vertices(first..self.mesh.count_vertices());
+        } else {
+            // The last quad was moved into the place of the quad.
+            self.dirty.mark_vertices(first..first + 4);
+        }
+        let indices = indices_of_mut(self.mesh)?;
+        for _ in 0..6 {
+            indices.pop();
+        }
+        Ok(())
     }
 
-    // The code from now on is a little messy, but it is very simple in actuality. It is mostly
-    // just offsetting the vertices and indices and formatting them into the right data-structures.
-
-    // offset the vertices, since we won't be using all the vertices of the the mesh,
-    // we need to find out which of them we will be using first, and then filter out
-    // the ones we dont need.
-    let mut offset: u32 = 0;
-    for q in sorted_vertices.iter() {
-        match q {
-            None => offset += 4,
-            Some(ref v) => {
-                let mut only_first = true;
-                for &i in v.iter() {
-                    let face = vertices_to_save[i as usize].2;
-                    vertices_to_save[i as usize].1 += face as u32 * 4 - offset;
-                    final_vertices.push(i);
-                    // update the vivi
-                    if only_first {
-                        vivi.insert(face, voxel_index, i + vertices_count as u32 - offset);
-                        only_first = false;
-                    }
-                }
-            }
+    fn push_quads(
+        &mut self,
+        _voxel_index: usize,
+        vals: &[VertexAttributeValues],
+        indices: &[u32],
+    ) -> Result<(), MeshemError> {
+        let vertices_count = self.mesh.count_vertices();
+        // Make sure the indices are usable before changing anything.
+        indices_of_mut(self.mesh)?;
+        for ((_, main_vals), new_vals) in self.mesh.attributes_mut().zip(vals) {
+            main_vals.extend(new_vals);
         }
+        let indices_main = indices_of_mut(self.mesh)?;
+        self.dirty
+            .mark_indices(indices_main.len()..indices_main.len() + indices.len());
+        indices_main.extend_from_slice(indices);
+        self.dirty
+            .mark_vertices(vertices_count..self.mesh.count_vertices());
+        Ok(())
     }
 
-    // offset the indices, we need to consider the fact that the indices wil be part of a big mesh,
-    // with a lot of vertices, so we must the vertices to a running count and offset them accordingly.
-    for i in indices_to_save.iter_mut() {
-        *i = vertices_to_save[*i as usize].1 + vertices_count as u32;
+    fn overwrite_quad(
+        &mut self,
+        attribute: &MeshVertexAttribute,
+        first: usize,
+        vals: &VertexAttributeValues,
+    ) -> Result<(), MeshemError> {
+        self.mesh
+            .attribute_mut(attribute.id)
+            .ok_or(MeshemError::MissingAttribute(attribute.name))?
+            .overwrite(first, vals);
+        self.dirty.mark_vertices(first..first + 4);
+        Ok(())
     }
-    indices_main.extend(indices_to_save);
-
-    for (attr, vals) in main_mesh.attributes_mut() {
-        let mut att = voxel
-            .attribute(attr.id)
-            .expect(format!("Couldn't retrieve voxel mesh attribute {:?}.", attr.id).as_str())
-            .get_needed(&final_vertices);
-        if attr.id == Mesh::ATTRIBUTE_POSITION.id {
-            att = att.offset_all(position_offset);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::prelude::*;
+
+    const DIMS: Dimensions = (5, 5, 5);
+
+    fn mesh(grid: &[u16]) -> (Mesh, MeshMD<u16>) {
+        try_mesh_grid(
+            DIMS,
+            &[],
+            grid,
+            &TestRegistry::new(),
+            MeshingAlgorithm::Culling,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn replacing_in_place_matches_breaking_and_adding() {
+        let reg = TestRegistry::new();
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut grid = random_grid(&mut rng, 125, 3);
+            let (mut fast, mut fast_md) = mesh(&grid);
+            let (mut slow, mut slow_md) = mesh(&grid);
+            let positions = positions_of(&fast).unwrap().clone();
+            let indices = indices_of(&fast).unwrap().clone();
+
+            let replaced: Vec<(usize, u16, u16)> = (0..125)
+                .filter(|i| grid[*i] != 0 && rng.gen_bool(0.3))
+                .map(|i| (i, grid[i], grid[i] % 3 + 1))
+                .collect();
+            assert!(!replaced.is_empty());
+            for (index, old, new) in replaced.iter().copied() {
+                let neighbors = neighbors_of(&grid, DIMS, index);
+                fast_md.log(VoxelChange::Replaced { old }, index, new, neighbors);
+                slow_md.log(VoxelChange::Broken, index, old, neighbors);
+                grid[index] = new;
+            }
+            update_mesh(&mut fast, &mut fast_md, &reg);
+            update_mesh(&mut slow, &mut slow_md, &reg);
+            for (index, _, new) in replaced.iter().copied() {
+                slow_md.log(
+                    VoxelChange::Added,
+                    index,
+                    new,
+                    neighbors_of(&grid, DIMS, index),
+                );
+            }
+            update_mesh(&mut slow, &mut slow_md, &reg);
+
+            // The quads were rewritten in place.
+            assert_eq!(positions_of(&fast).unwrap(), &positions);
+            assert_eq!(indices_of(&fast).unwrap(), &indices);
+            assert_ne!(positions_of(&slow).unwrap(), &positions);
+
+            let grid = FlatGrid::new(DIMS, grid);
+            assert!(fast_md.verify(&fast, &grid, &reg).is_ok());
+            assert!(slow_md.verify(&slow, &grid, &reg).is_ok());
         }
-        vals.extend(&att);
     }
 }
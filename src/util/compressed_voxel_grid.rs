@@ -1,70 +1,533 @@
-#![allow(dead_code)]
-#[derive(Debug, PartialEq)]
-struct Run {
-    value: bool,
-    length: usize,
+//! Compressed data-structures for storing voxels.
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Run<T> {
+    value: T,
+    // The index right after the last voxel of the run.
+    end: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct RleVec {
-    data: Vec<Run>,
+/// A generic run-length-encoded vector. Consecutive equal voxels are stored once, so it's ideal
+/// for mostly-air (or mostly-stone) chunks, columns of voxels and compact save files.
+/// Random access is O(log n) in the number of runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rle<T> {
+    runs: Vec<Run<T>>,
 }
 
-impl RleVec {
+impl<T> Default for Rle<T> {
+    fn default() -> Self {
+        Rle { runs: Vec::new() }
+    }
+}
+
+impl<T: Copy + PartialEq> Rle<T> {
     pub fn new() -> Self {
-        RleVec { data: Vec::new() }
+        Rle { runs: Vec::new() }
     }
 
-    pub fn push(&mut self, value: bool, length: usize) {
-        match self.data.last_mut() {
-            Some(last_run) if last_run.value == value => {
-                last_run.length += length;
-            }
-            _ => {
-                self.data.push(Run { value, length });
+    /// A vector of `length` copies of `value`.
+    pub fn from_value(value: T, length: usize) -> Self {
+        let mut r = Self::new();
+        r.push(value, length);
+        r
+    }
+
+    /// Encode a one dimensional grid.
+    pub fn from_slice(values: &[T]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    /// Decode into a one dimensional grid.
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    /// The amount of values (not runs).
+    pub fn len(&self) -> usize {
+        self.runs.last().map_or(0, |r| r.end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The amount of runs.
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Append `length` copies of `value`.
+    pub fn push(&mut self, value: T, length: usize) {
+        if length == 0 {
+            return;
+        }
+        let end = self.len() + length;
+        match self.runs.last_mut() {
+            Some(last_run) if last_run.value == value => last_run.end = end,
+            _ => self.runs.push(Run { value, end }),
+        }
+    }
+
+    /// The value at `index`, None if out of bounds.
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.runs.get(self.run_of(index)).map(|r| r.value)
+    }
+
+    /// Set the value at `index`, splitting and merging runs as needed. Returns the value that
+    /// was there before, or None if `index` is out of bounds (in which case nothing changes).
+    pub fn set(&mut self, index: usize, value: T) -> Option<T> {
+        let i = self.run_of(index);
+        let run = *self.runs.get(i)?;
+        if run.value == value {
+            return Some(value);
+        }
+        let start = self.run_start(i);
+        let mut new_runs: Vec<Run<T>> = Vec::with_capacity(3);
+        if index > start {
+            new_runs.push(Run {
+                value: run.value,
+                end: index,
+            });
+        }
+        new_runs.push(Run {
+            value,
+            end: index + 1,
+        });
+        if index + 1 < run.end {
+            new_runs.push(run);
+        }
+        self.runs.splice(i..=i, new_runs);
+
+        // Merge the new run with its neighbors if they hold the same value.
+        let j = self.run_of(index);
+        if j + 1 < self.runs.len() && self.runs[j + 1].value == value {
+            self.runs[j].end = self.runs[j + 1].end;
+            self.runs.remove(j + 1);
+        }
+        if j > 0 && self.runs[j - 1].value == value {
+            self.runs[j - 1].end = self.runs[j].end;
+            self.runs.remove(j);
+        }
+        Some(run.value)
+    }
+
+    /// Iterate over the runs, as (value, length).
+    pub fn runs(&self) -> impl Iterator<Item = (T, usize)> + '_ {
+        self.runs
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.value, r.end - self.run_start(i)))
+    }
+
+    /// Iterate over the values.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.runs()
+            .flat_map(|(value, length)| std::iter::repeat_n(value, length))
+    }
+
+    // The index of the run that contains `index`, `self.runs.len()` if out of bounds.
+    fn run_of(&self, index: usize) -> usize {
+        self.runs.partition_point(|r| r.end <= index)
+    }
+
+    fn run_start(&self, run: usize) -> usize {
+        match run {
+            0 => 0,
+            _ => self.runs[run - 1].end,
+        }
+    }
+}
+
+impl<T: Copy + PartialEq> FromIterator<T> for Rle<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut r = Rle::new();
+        for value in iter {
+            r.push(value, 1);
+        }
+        r
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGrid<T> for FlatGrid<Rle<T>> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.voxels
+            .get(one_d_cords(cords, self.dims))
+            .expect("The grid is smaller than its dimensions.")
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGridMut<T> for FlatGrid<Rle<T>> {
+    fn set(&mut self, cords: [usize; 3], voxel: T) -> T {
+        self.voxels
+            .set(one_d_cords(cords, self.dims), voxel)
+            .expect("The grid is smaller than its dimensions.")
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGrid<T> for FlatGrid<&Rle<T>> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.voxels
+            .get(one_d_cords(cords, self.dims))
+            .expect("The grid is smaller than its dimensions.")
+    }
+}
+
+/// A palette-compressed grid of voxels. Each distinct voxel is stored once in the palette, and
+/// the grid itself only stores bit-packed indices into the palette, so a chunk with a handful of
+/// voxel types takes a fraction of the memory of a flat `Vec<T>`. The bit width grows
+/// automatically as new voxel types are set. It implements [`VoxelGrid`], so it can be meshed
+/// directly with `mesh_voxel_grid`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PalettedGrid<T> {
+    dims: Dimensions,
+    palette: Vec<T>,
+    // bits per index, always a power of two (or 0 when there is only one voxel type).
+    bits: u32,
+    data: Vec<u64>,
+}
+
+impl<T: Copy + PartialEq> PalettedGrid<T> {
+    /// A grid filled with `voxel`.
+    pub fn new(dims: Dimensions, voxel: T) -> Self {
+        PalettedGrid {
+            dims,
+            palette: vec![voxel],
+            bits: 0,
+            data: vec![],
+        }
+    }
+
+    /// Compress a one dimensional grid, in the layout used across the crate. Returns an error if
+    /// the length of the grid isn't the product of the dimensions.
+    pub fn from_slice(dims: Dimensions, grid: &[T]) -> Result<Self, MeshemError> {
+        MeshemError::check_grid_size(dims, grid.len())?;
+        Ok(Self::compress(dims, grid))
+    }
+
+    // Compress a grid whose length was already checked.
+    fn compress(dims: Dimensions, grid: &[T]) -> Self {
+        let mut palette: Vec<T> = vec![];
+        let indices: Vec<u64> = grid
+            .iter()
+            .map(|v| match palette.iter().position(|p| p == v) {
+                Some(i) => i as u64,
+                None => {
+                    palette.push(*v);
+                    palette.len() as u64 - 1
+                }
+            })
+            .collect();
+        if palette.is_empty() {
+            return PalettedGrid {
+                dims,
+                palette,
+                bits: 0,
+                data: vec![],
+            };
+        }
+        let bits = bits_needed(palette.len());
+        let mut r = PalettedGrid {
+            dims,
+            palette,
+            bits,
+            data: vec![0; words_needed(grid.len(), bits)],
+        };
+        for (i, p) in indices.into_iter().enumerate() {
+            r.write(i, p);
+        }
+        r
+    }
+
+    /// Decompress into a one dimensional grid, in the layout used across the crate.
+    pub fn to_vec(&self) -> Vec<T> {
+        (0..self.len()).map(|i| self.get_index(i)).collect()
+    }
+
+    /// The number of voxels in the grid.
+    pub fn len(&self) -> usize {
+        self.dims.0 * self.dims.1 * self.dims.2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The distinct voxels that are (or were) in the grid.
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    /// The voxel at `index` of the one dimensional grid.
+    pub fn get_index(&self, index: usize) -> T {
+        assert!(index < self.len(), "Index out of bounds of the grid.");
+        self.palette[self.read(index) as usize]
+    }
+
+    /// Set the voxel at `cords`, returns the voxel that was there before.
+    pub fn set(&mut self, cords: [usize; 3], voxel: T) -> T {
+        self.set_index(one_d_cords(cords, self.dims), voxel)
+    }
+
+    /// Set the voxel at `index` of the one dimensional grid, returns the voxel that was there
+    /// before.
+    pub fn set_index(&mut self, index: usize, voxel: T) -> T {
+        let old = self.get_index(index);
+        if old == voxel {
+            return old;
+        }
+        let p = match self.palette.iter().position(|p| *p == voxel) {
+            Some(p) => p,
+            None => {
+                self.palette.push(voxel);
+                let bits = bits_needed(self.palette.len());
+                if bits > self.bits {
+                    self.resize(bits);
+                }
+                self.palette.len() - 1
             }
+        };
+        self.write(index, p as u64);
+        old
+    }
+
+    /// Remove the voxels that are no longer in the grid from the palette, and shrink the bit
+    /// width if possible.
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len() {
+            used[self.read(i) as usize] = true;
+        }
+        if used.iter().all(|u| *u) {
+            return;
         }
+        let voxels = self.to_vec();
+        *self = Self::compress(self.dims, &voxels);
     }
 
-    pub fn get(&self, index: usize) -> Option<bool> {
-        let mut run_start = 0;
-        for run in &self.data {
-            if index < run_start + run.length {
-                return Some(run.value);
+    /// The amount of bytes used for storing the voxels (without the palette).
+    pub fn data_size(&self) -> usize {
+        self.data.len() * std::mem::size_of::<u64>()
+    }
+
+    fn resize(&mut self, bits: u32) {
+        let indices: Vec<u64> = (0..self.len()).map(|i| self.read(i)).collect();
+        self.bits = bits;
+        self.data = vec![0; words_needed(indices.len(), bits)];
+        for (i, p) in indices.into_iter().enumerate() {
+            self.write(i, p);
+        }
+    }
+
+    fn read(&self, index: usize) -> u64 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        (self.data[index / per_word] >> shift) & mask(self.bits)
+    }
+
+    fn write(&mut self, index: usize, value: u64) {
+        if self.bits == 0 {
+            return;
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let word = &mut self.data[index / per_word];
+        *word = (*word & !(mask(self.bits) << shift)) | (value << shift);
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGrid<T> for PalettedGrid<T> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.get_index(one_d_cords(cords, self.dims))
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGridMut<T> for PalettedGrid<T> {
+    fn set(&mut self, cords: [usize; 3], voxel: T) -> T {
+        PalettedGrid::set(self, cords, voxel)
+    }
+}
+
+// The amount of bits needed to index a palette of this length, rounded up to a power of two so
+// the indices never cross the boundary of a word.
+fn bits_needed(palette_len: usize) -> u32 {
+    if palette_len <= 1 {
+        return 0;
+    }
+    let bits = usize::BITS - (palette_len - 1).leading_zeros();
+    bits.next_power_of_two()
+}
+
+fn words_needed(len: usize, bits: u32) -> usize {
+    if bits == 0 {
+        return 0;
+    }
+    len.div_ceil(64 / bits as usize)
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    // The runs are never empty, and neighboring runs never hold the same value.
+    fn assert_canonical<T: Copy + PartialEq + std::fmt::Debug>(rle: &Rle<T>) {
+        let runs: Vec<(T, usize)> = rle.runs().collect();
+        assert!(runs.iter().all(|(_, length)| *length > 0), "{:?}", runs);
+        assert!(runs.windows(2).all(|w| w[0].0 != w[1].0), "{:?}", runs);
+    }
+
+    #[test]
+    fn rle_set_matches_vec() {
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let len = rng.gen_range(1..40);
+            let mut expected: Vec<u8> = (0..len).map(|_| rng.gen_range(0..3)).collect();
+            let mut rle = Rle::from_slice(&expected);
+            for _ in 0..100 {
+                // Favor the edges of the vector and of the runs, where runs split and merge.
+                let run_edges: Vec<usize> = rle
+                    .runs()
+                    .scan(0, |start, (_, length)| {
+                        *start += length;
+                        Some([*start - length, *start - 1])
+                    })
+                    .flatten()
+                    .collect();
+                let index = match rng.gen_range(0..4) {
+                    0 => 0,
+                    1 => len - 1,
+                    2 => run_edges[rng.gen_range(0..run_edges.len())],
+                    _ => rng.gen_range(0..len),
+                };
+                let value = rng.gen_range(0..3);
+                assert_eq!(rle.set(index, value), Some(expected[index]));
+                expected[index] = value;
+                assert_eq!(rle.to_vec(), expected);
+                assert_eq!(rle.len(), len);
+                assert_canonical(&rle);
             }
-            run_start += run.length;
-        }
-        None
-    }
-
-    pub fn change(&mut self, index: usize, value: bool) -> Result<(), &'static str> {
-        let mut run_start = 0;
-        for run in &mut self.data {
-            if index < run_start + run.length {
-                if run.length == 1 {
-                    run.value = value;
-                } else {
-                    let offset = index - run_start;
-                    if offset == 0 {
-                        run.length -= 1;
-                        self.data.insert(index, Run { value, length: 1 });
-                    } else if offset == run.length - 1 {
-                        run.length -= 1;
-                        self.data.insert(index + 1, Run { value, length: 1 });
-                    } else {
-                        let new_run = Run {
-                            value,
-                            length: run.length - offset - 1,
-                        };
-                        run.length = offset;
-                        self.data.insert(index + 1, Run { value, length: 1 });
-                        self.data.insert(index + 2, new_run);
-                    }
+            for (index, value) in expected.iter().enumerate() {
+                assert_eq!(rle.get(index), Some(*value));
+            }
+            assert_eq!(rle, Rle::from_slice(&expected));
+        }
+    }
+
+    #[test]
+    fn rle_set_edge_cases() {
+        let mut rle = Rle::from_value(1u8, 4);
+        assert_eq!(rle.set(4, 2), None);
+        assert_eq!(rle.get(4), None);
+        assert_eq!(rle.to_vec(), vec![1; 4]);
+        assert_eq!(Rle::<u8>::new().set(0, 1), None);
+        // Splitting a run in the middle, and merging it back.
+        assert_eq!(rle.set(2, 2), Some(1));
+        assert_eq!(rle.run_count(), 3);
+        assert_eq!(rle.set(2, 1), Some(2));
+        assert_eq!(rle.run_count(), 1);
+    }
+
+    #[test]
+    fn paletted_grid_round_trips_every_bit_width() {
+        for seed in 0..4 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let dims = (5, 6, 7);
+            let mut grid = PalettedGrid::new(dims, 0u32);
+            let mut expected = vec![0u32; grid.len()];
+            // Growing the palette goes through every bit width up to 16.
+            for (types, bits) in [(1, 0), (2, 1), (4, 2), (16, 4), (256, 8), (300, 16)] {
+                for _ in 0..grid.len() {
+                    let index = rng.gen_range(0..grid.len());
+                    let voxel = rng.gen_range(0..types);
+                    assert_eq!(grid.set_index(index, voxel), expected[index]);
+                    expected[index] = voxel;
+                }
+                // Make sure every voxel type made it into the palette.
+                for voxel in 0..types {
+                    let index = rng.gen_range(0..grid.len());
+                    grid.set_index(index, voxel);
+                    expected[index] = voxel;
+                }
+                assert_eq!(grid.bits, bits);
+                assert_eq!(grid.to_vec(), expected);
+                for (index, voxel) in expected.iter().enumerate() {
+                    assert_eq!(grid.get(three_d_cords_arr(index, dims)), *voxel);
                 }
-                return Ok(());
+                assert_eq!(
+                    PalettedGrid::from_slice(dims, &expected).unwrap().to_vec(),
+                    expected
+                );
             }
-            run_start += run.length;
+
+            // 32 bits need more than 2^16 voxel types, so the packing is checked directly.
+            grid.resize(32);
+            assert_eq!(grid.to_vec(), expected);
+            let indices: Vec<u64> = (0..grid.len()).map(|_| rng.gen()).collect();
+            for (index, p) in indices.iter().enumerate() {
+                grid.write(index, *p & mask(32));
+            }
+            for (index, p) in indices.iter().enumerate() {
+                assert_eq!(grid.read(index), *p & mask(32));
+            }
+        }
+    }
+
+    #[test]
+    fn paletted_grid_compact_keeps_voxels() {
+        let dims = (4, 4, 4);
+        // Every voxel type from 0 to 19.
+        let mut expected: Vec<u16> = (0..64).map(|i| i * 7 % 20).collect();
+        let mut grid = PalettedGrid::from_slice(dims, &expected).unwrap();
+        assert_eq!(grid.bits, 8);
+        // Only 3 voxel types are left.
+        for (index, voxel) in expected.iter_mut().enumerate() {
+            *voxel %= 3;
+            grid.set_index(index, *voxel);
         }
-        Err("Index out of bounds")
+        assert_eq!(grid.palette().len(), 20);
+        grid.compact();
+        assert_eq!(grid.to_vec(), expected);
+        assert_eq!(grid.palette().len(), 3);
+        assert_eq!(grid.bits, 2);
+        // Nothing to remove.
+        let before = grid.clone();
+        grid.compact();
+        assert_eq!(grid, before);
+    }
+
+    #[test]
+    fn paletted_grid_size_mismatch() {
+        assert_eq!(
+            PalettedGrid::from_slice((2, 2, 2), &[0u8; 7]),
+            Err(MeshemError::GridSizeMismatch {
+                expected: 8,
+                found: 7
+            })
+        );
     }
 }
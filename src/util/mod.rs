@@ -1,15 +1,12 @@
-pub(crate) mod compressed_voxel_grid;
+pub mod compressed_voxel_grid;
 pub mod direction;
+pub mod octree;
 pub mod vav;
 
 use crate::prelude::{Dimensions, Face, Face::*};
 pub use direction::Direction::*;
 pub use direction::*;
 
-pub(crate) fn in_range(x: usize, bot: usize, top: usize) -> bool {
-    return bot <= x && x < top;
-}
-
 use bevy::prelude::Vec3;
 
 pub fn position_to_chunk(pos: Vec3, chunk_dims: (usize, usize, usize)) -> [i32; 2] {
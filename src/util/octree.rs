@@ -0,0 +1,355 @@
+//! A sparse voxel octree, for large and mostly empty (or mostly uniform) areas where a dense
+//! grid would be wasteful. Regions that hold a single voxel type are collapsed into one node.
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node<T> {
+    Leaf(T),
+    // Children are indexed by octant: bit 0 is the x half, bit 1 the y half, bit 2 the z half.
+    Branch(Box<[Node<T>; 8]>),
+}
+
+/// A sparse voxel octree covering a grid of the given dimensions. Voxels that were never set are
+/// `empty`. It implements [`VoxelGrid`], and it can be meshed with `mesh_octree`, which skips
+/// empty and uniform regions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Octree<T> {
+    dims: Dimensions,
+    // The side of the cube the root covers, a power of two.
+    size: usize,
+    empty: T,
+    root: Node<T>,
+}
+
+impl<T: Copy + PartialEq> Octree<T> {
+    /// An octree where all the voxels are `empty`.
+    pub fn new(dims: Dimensions, empty: T) -> Self {
+        Octree {
+            dims,
+            size: dims.0.max(dims.1).max(dims.2).max(1).next_power_of_two(),
+            empty,
+            root: Node::Leaf(empty),
+        }
+    }
+
+    /// Build an octree from a one dimensional grid, in the layout used across the crate.
+    pub fn from_slice(dims: Dimensions, grid: &[T], empty: T) -> Result<Self, MeshemError> {
+        MeshemError::check_grid_size(dims, grid.len())?;
+        let mut r = Self::new(dims, empty);
+        for (i, v) in grid.iter().enumerate() {
+            if *v != empty {
+                r.insert(three_d_cords_arr(i, dims), *v);
+            }
+        }
+        Ok(r)
+    }
+
+    /// The voxel that fills the parts of the octree that were never set.
+    pub fn empty(&self) -> T {
+        self.empty
+    }
+
+    /// The voxel at `cords`, None if `cords` is out of the bounds of the octree.
+    pub fn query(&self, cords: [usize; 3]) -> Option<T> {
+        one_d_cords_safe(cords, self.dims)?;
+        let mut node = &self.root;
+        let mut size = self.size;
+        loop {
+            match node {
+                Node::Leaf(v) => return Some(*v),
+                Node::Branch(children) => {
+                    size /= 2;
+                    node = &children[octant(cords, size)];
+                }
+            }
+        }
+    }
+
+    /// Set the voxel at `cords`, returns the voxel that was there before, or None (and changes
+    /// nothing) if `cords` is out of the bounds of the octree.
+    pub fn insert(&mut self, cords: [usize; 3], voxel: T) -> Option<T> {
+        let old = self.query(cords)?;
+        if old != voxel {
+            set_node(&mut self.root, self.size, cords, voxel);
+        }
+        Some(old)
+    }
+
+    /// Set the voxel at `cords` back to the empty voxel, returns the voxel that was there before,
+    /// or None if `cords` is out of the bounds of the octree.
+    pub fn remove(&mut self, cords: [usize; 3]) -> Option<T> {
+        self.insert(cords, self.empty)
+    }
+
+    /// The amount of nodes in the octree (leaves and branches).
+    pub fn node_count(&self) -> usize {
+        fn count<T>(node: &Node<T>) -> usize {
+            match node {
+                Node::Leaf(_) => 1,
+                Node::Branch(children) => 1 + children.iter().map(count).sum::<usize>(),
+            }
+        }
+        count(&self.root)
+    }
+
+    /// Iterate over the uniform regions of the octree, as (origin, side length, voxel).
+    /// The regions are cubes, and may stick out of the dimensions of the octree.
+    pub fn regions(&self) -> Vec<([usize; 3], usize, T)> {
+        fn collect<T: Copy>(
+            node: &Node<T>,
+            origin: [usize; 3],
+            size: usize,
+            dims: Dimensions,
+            r: &mut Vec<([usize; 3], usize, T)>,
+        ) {
+            if origin[0] >= dims.0 || origin[1] >= dims.1 || origin[2] >= dims.2 {
+                return;
+            }
+            match node {
+                Node::Leaf(v) => r.push((origin, size, *v)),
+                Node::Branch(children) => {
+                    let half = size / 2;
+                    for (i, child) in children.iter().enumerate() {
+                        let o = [
+                            origin[0] + (i & 1) * half,
+                            origin[1] + ((i >> 1) & 1) * half,
+                            origin[2] + ((i >> 2) & 1) * half,
+                        ];
+                        collect(child, o, half, dims, r);
+                    }
+                }
+            }
+        }
+        let mut r = vec![];
+        collect(&self.root, [0, 0, 0], self.size, self.dims, &mut r);
+        r
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGrid<T> for Octree<T> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.query(cords)
+            .expect("Coordinates out of the bounds of the octree.")
+    }
+}
+
+impl<T: Copy + PartialEq> VoxelGridMut<T> for Octree<T> {
+    fn set(&mut self, cords: [usize; 3], voxel: T) -> T {
+        self.insert(cords, voxel)
+            .expect("Coordinates out of the bounds of the octree.")
+    }
+}
+
+// The child (of a node with children of side `half`) that contains `cords`.
+fn octant(cords: [usize; 3], half: usize) -> usize {
+    (cords[0] & half != 0) as usize
+        | ((cords[1] & half != 0) as usize) << 1
+        | ((cords[2] & half != 0) as usize) << 2
+}
+
+fn set_node<T: Copy + PartialEq>(node: &mut Node<T>, size: usize, cords: [usize; 3], voxel: T) {
+    if size == 1 {
+        *node = Node::Leaf(voxel);
+        return;
+    }
+    if let Node::Leaf(v) = *node {
+        *node = Node::Branch(Box::new(std::array::from_fn(|_| Node::Leaf(v))));
+    }
+    let Node::Branch(ref mut children) = node else {
+        unreachable!();
+    };
+    set_node(&mut children[octant(cords, size / 2)], size / 2, cords, voxel);
+
+    // Collapse the node if all of its children hold the same voxel.
+    if let Node::Leaf(first) = children[0] {
+        if children
+            .iter()
+            .all(|c| matches!(c, Node::Leaf(v) if *v == first))
+        {
+            *node = Node::Leaf(first);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::prelude::*;
+
+    // A grid of random boxes, so the octree has uniform regions of every size.
+    fn boxy_grid(rng: &mut StdRng, dims: Dimensions) -> Vec<u16> {
+        let mut grid = vec![0u16; dims.0 * dims.1 * dims.2];
+        for _ in 0..rng.gen_range(0..6) {
+            let voxel = rng.gen_range(0..=4);
+            let min = [
+                rng.gen_range(0..dims.0),
+                rng.gen_range(0..dims.1),
+                rng.gen_range(0..dims.2),
+            ];
+            let max = [
+                rng.gen_range(min[0]..dims.0) + 1,
+                rng.gen_range(min[1]..dims.1) + 1,
+                rng.gen_range(min[2]..dims.2) + 1,
+            ];
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    for x in min[0]..max[0] {
+                        grid[one_d_cords([x, y, z], dims)] = voxel;
+                    }
+                }
+            }
+        }
+        for _ in 0..rng.gen_range(0..8) {
+            let index = rng.gen_range(0..grid.len());
+            grid[index] = rng.gen_range(0..=4);
+        }
+        grid
+    }
+
+    #[test]
+    fn mesh_octree_matches_mesh_grid() {
+        let reg = TestRegistry::new();
+        for seed in 0..40 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let dims = (
+                rng.gen_range(1..10),
+                rng.gen_range(1..10),
+                rng.gen_range(1..10),
+            );
+            let len = dims.0 * dims.1 * dims.2;
+            let grid = match rng.gen_bool(0.5) {
+                true => boxy_grid(&mut rng, dims),
+                false => random_grid(&mut rng, len, 4),
+            };
+            let outer_layer: Vec<Face> = (0..6)
+                .map(Face::from)
+                .filter(|_| rng.gen_bool(0.3))
+                .collect();
+            let octree = Octree::from_slice(dims, &grid, 0).unwrap();
+            let (mesh, _) = mesh_octree(&outer_layer, &octree, &reg, None).unwrap();
+            let (expected, _) = try_mesh_grid(
+                dims,
+                &outer_layer,
+                &grid,
+                &reg,
+                MeshingAlgorithm::Culling,
+                None,
+            )
+            .unwrap();
+            assert_same_mesh(&mesh, &expected, &format!("seed {}, {:?}", seed, dims));
+        }
+    }
+
+    #[test]
+    fn set_node_inserts_removes_and_collapses() {
+        let mut octree = Octree::new((4, 4, 4), 0u16);
+        assert_eq!(octree.node_count(), 1);
+
+        // Setting one voxel splits every node on the way down to it.
+        assert_eq!(octree.insert([1, 2, 3], 5), Some(0));
+        assert_eq!(octree.node_count(), 17);
+        assert_eq!(octree.query([1, 2, 3]), Some(5));
+        assert_eq!(octree.query([1, 2, 2]), Some(0));
+        // Setting it to what it already is changes nothing.
+        assert_eq!(octree.insert([1, 2, 3], 5), Some(5));
+        assert_eq!(octree.node_count(), 17);
+
+        // Removing it collapses the nodes back into one leaf.
+        assert_eq!(octree.remove([1, 2, 3]), Some(5));
+        assert_eq!(octree.node_count(), 1);
+        assert_eq!(octree, Octree::new((4, 4, 4), 0));
+
+        // Filling an octant collapses it into a leaf, and filling all of them collapses the root.
+        for octant in 0..8 {
+            let origin = [
+                (octant & 1) * 2,
+                (octant >> 1 & 1) * 2,
+                (octant >> 2 & 1) * 2,
+            ];
+            for i in 0..8 {
+                let cords = [
+                    origin[0] + (i & 1),
+                    origin[1] + (i >> 1 & 1),
+                    origin[2] + (i >> 2 & 1),
+                ];
+                octree.insert(cords, 7);
+            }
+            if octant < 7 {
+                assert_eq!(octree.node_count(), 9);
+            }
+        }
+        assert_eq!(octree.node_count(), 1);
+        assert_eq!(octree.regions(), vec![([0, 0, 0], 4, 7)]);
+    }
+
+    #[test]
+    fn set_node_matches_grid() {
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let dims = (5, 3, 7);
+            let mut octree = Octree::new(dims, 0u16);
+            let mut expected = vec![0u16; dims.0 * dims.1 * dims.2];
+            for _ in 0..500 {
+                let index = rng.gen_range(0..expected.len());
+                let voxel = rng.gen_range(0..3);
+                let cords = three_d_cords_arr(index, dims);
+                assert_eq!(octree.insert(cords, voxel), Some(expected[index]));
+                expected[index] = voxel;
+            }
+            for (index, voxel) in expected.iter().enumerate() {
+                assert_eq!(octree.query(three_d_cords_arr(index, dims)), Some(*voxel));
+            }
+            // The same voxels collapse into the same tree, however they were set.
+            assert_eq!(octree, Octree::from_slice(dims, &expected, 0).unwrap());
+
+            // The root covers 8x8x8, but anything outside of the dimensions is out of bounds.
+            let before = octree.clone();
+            assert_eq!(octree.query([0, 3, 0]), None);
+            assert_eq!(octree.insert([5, 0, 0], 1), None);
+            assert_eq!(octree.remove([0, 0, 7]), None);
+            assert_eq!(octree, before);
+            assert_eq!(
+                Octree::from_slice(dims, &expected[1..], 0),
+                Err(MeshemError::GridSizeMismatch {
+                    expected: expected.len(),
+                    found: expected.len() - 1
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn regions_are_clipped_to_dims() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let dims = (
+                rng.gen_range(1..12),
+                rng.gen_range(1..12),
+                rng.gen_range(1..12),
+            );
+            let grid = boxy_grid(&mut rng, dims);
+            let octree = Octree::from_slice(dims, &grid, 0).unwrap();
+            // Every voxel of the grid is in exactly one region, and no region starts outside it.
+            let mut covered = vec![0; grid.len()];
+            for (origin, size, voxel) in octree.regions() {
+                assert!(origin[0] < dims.0 && origin[1] < dims.1 && origin[2] < dims.2);
+                assert!(size.is_power_of_two());
+                for y in origin[1]..(origin[1] + size).min(dims.1) {
+                    for z in origin[2]..(origin[2] + size).min(dims.2) {
+                        for x in origin[0]..(origin[0] + size).min(dims.0) {
+                            let index = one_d_cords([x, y, z], dims);
+                            assert_eq!(grid[index], voxel);
+                            covered[index] += 1;
+                        }
+                    }
+                }
+            }
+            assert!(covered.iter().all(|c| *c == 1), "{:?}", dims);
+        }
+    }
+}
@@ -15,6 +15,10 @@ pub trait VAVutils {
     fn get_needed(&self, needed_values: &Vec<u32>) -> VertexAttributeValues;
     fn swap_remove(&mut self, index: usize);
     fn remove(&mut self, index: usize);
+    fn reserve(&mut self, additional: usize);
+    fn shrink_to_fit(&mut self);
+    // method overwrites the values starting at `start` with the values of `t`.
+    fn overwrite(&mut self, start: usize, t: &Self);
 }
 
 impl VAVutils for VertexAttributeValues {
@@ -27,6 +31,8 @@ impl VAVutils for VertexAttributeValues {
                         .collect(),
                 );
             }
+            // Tangents are directions, they stay the same wherever the voxel is.
+            VertexAttributeValues::Float32x4(vals) => VertexAttributeValues::Float32x4(vals.clone()),
             _ => panic!("Method offset_all only works for Float32x3 (the standard for position)"),
         }
     }
@@ -443,67 +449,246 @@ impl VAVutils for VertexAttributeValues {
     // and returns the needed values in the same order.
     fn get_needed(&self, needed_values: &Vec<u32>) -> VertexAttributeValues {
         match self {
-            VertexAttributeValues::Float32(ref vals) => {
-                let mut to_return = VertexAttributeValues::Float32(vec![]);
-                let VertexAttributeValues::Float32(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            VertexAttributeValues::Sint32(ref vals) => {
-                let mut to_return = VertexAttributeValues::Sint32(vec![]);
-                let VertexAttributeValues::Sint32(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            VertexAttributeValues::Uint32(ref vals) => {
-                let mut to_return = VertexAttributeValues::Uint32(vec![]);
-                let VertexAttributeValues::Uint32(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            VertexAttributeValues::Float32x2(ref vals) => {
-                let mut to_return = VertexAttributeValues::Float32x2(vec![]);
-                let VertexAttributeValues::Float32x2(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            VertexAttributeValues::Float32x3(ref vals) => {
-                let mut to_return = VertexAttributeValues::Float32x3(vec![]);
-                let VertexAttributeValues::Float32x3(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            VertexAttributeValues::Float32x4(ref vals) => {
-                let mut to_return = VertexAttributeValues::Float32x4(vec![]);
-                let VertexAttributeValues::Float32x4(ref mut values) = to_return else {
-                    panic!("This shoudln't happen.");
-                };
-                for &i in needed_values.iter() {
-                    values.push(vals[i as usize]);
-                }
-                return to_return;
-            }
-            _ => panic!("This variant isn't supported"),
+            VertexAttributeValues::Float32(vals) => VertexAttributeValues::Float32(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint32(vals) => VertexAttributeValues::Sint32(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint32(vals) => VertexAttributeValues::Uint32(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Float32x2(vals) => VertexAttributeValues::Float32x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint32x2(vals) => VertexAttributeValues::Sint32x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint32x2(vals) => VertexAttributeValues::Uint32x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Float32x3(vals) => VertexAttributeValues::Float32x3(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint32x3(vals) => VertexAttributeValues::Sint32x3(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint32x3(vals) => VertexAttributeValues::Uint32x3(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Float32x4(vals) => VertexAttributeValues::Float32x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint32x4(vals) => VertexAttributeValues::Sint32x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint32x4(vals) => VertexAttributeValues::Uint32x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint16x2(vals) => VertexAttributeValues::Sint16x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Snorm16x2(vals) => VertexAttributeValues::Snorm16x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint16x2(vals) => VertexAttributeValues::Uint16x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Unorm16x2(vals) => VertexAttributeValues::Unorm16x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint16x4(vals) => VertexAttributeValues::Sint16x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Snorm16x4(vals) => VertexAttributeValues::Snorm16x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint16x4(vals) => VertexAttributeValues::Uint16x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Unorm16x4(vals) => VertexAttributeValues::Unorm16x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint8x2(vals) => VertexAttributeValues::Sint8x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Snorm8x2(vals) => VertexAttributeValues::Snorm8x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint8x2(vals) => VertexAttributeValues::Uint8x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Unorm8x2(vals) => VertexAttributeValues::Unorm8x2(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Sint8x4(vals) => VertexAttributeValues::Sint8x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Snorm8x4(vals) => VertexAttributeValues::Snorm8x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Uint8x4(vals) => VertexAttributeValues::Uint8x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+            VertexAttributeValues::Unorm8x4(vals) => VertexAttributeValues::Unorm8x4(
+                needed_values.iter().map(|&i| vals[i as usize]).collect(),
+            ),
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        match self {
+            VertexAttributeValues::Float32(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint32(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint32(vals) => vals.reserve(additional),
+            VertexAttributeValues::Float32x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint32x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint32x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Float32x3(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint32x3(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint32x3(vals) => vals.reserve(additional),
+            VertexAttributeValues::Float32x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint32x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint32x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint16x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Snorm16x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint16x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Unorm16x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint16x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Snorm16x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint16x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Unorm16x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint8x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Snorm8x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint8x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Unorm8x2(vals) => vals.reserve(additional),
+            VertexAttributeValues::Sint8x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Snorm8x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Uint8x4(vals) => vals.reserve(additional),
+            VertexAttributeValues::Unorm8x4(vals) => vals.reserve(additional),
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        match self {
+            VertexAttributeValues::Float32(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint32(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint32(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Float32x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint32x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint32x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Float32x3(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint32x3(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint32x3(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Float32x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint32x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint32x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint16x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Snorm16x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint16x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Unorm16x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint16x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Snorm16x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint16x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Unorm16x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint8x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Snorm8x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint8x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Unorm8x2(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Sint8x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Snorm8x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Uint8x4(vals) => vals.shrink_to_fit(),
+            VertexAttributeValues::Unorm8x4(vals) => vals.shrink_to_fit(),
+        }
+    }
+
+    fn overwrite(&mut self, start: usize, t: &VertexAttributeValues) {
+        match (self, t) {
+            (VertexAttributeValues::Float32(vals), VertexAttributeValues::Float32(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint32(vals), VertexAttributeValues::Sint32(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint32(vals), VertexAttributeValues::Uint32(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Float32x2(vals), VertexAttributeValues::Float32x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint32x2(vals), VertexAttributeValues::Sint32x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint32x2(vals), VertexAttributeValues::Uint32x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Float32x3(vals), VertexAttributeValues::Float32x3(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint32x3(vals), VertexAttributeValues::Sint32x3(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint32x3(vals), VertexAttributeValues::Uint32x3(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Float32x4(vals), VertexAttributeValues::Float32x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint32x4(vals), VertexAttributeValues::Sint32x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint32x4(vals), VertexAttributeValues::Uint32x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint16x2(vals), VertexAttributeValues::Sint16x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Snorm16x2(vals), VertexAttributeValues::Snorm16x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint16x2(vals), VertexAttributeValues::Uint16x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Unorm16x2(vals), VertexAttributeValues::Unorm16x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint16x4(vals), VertexAttributeValues::Sint16x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Snorm16x4(vals), VertexAttributeValues::Snorm16x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint16x4(vals), VertexAttributeValues::Uint16x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Unorm16x4(vals), VertexAttributeValues::Unorm16x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint8x2(vals), VertexAttributeValues::Sint8x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Snorm8x2(vals), VertexAttributeValues::Snorm8x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint8x2(vals), VertexAttributeValues::Uint8x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Unorm8x2(vals), VertexAttributeValues::Unorm8x2(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Sint8x4(vals), VertexAttributeValues::Sint8x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Snorm8x4(vals), VertexAttributeValues::Snorm8x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Uint8x4(vals), VertexAttributeValues::Uint8x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            (VertexAttributeValues::Unorm8x4(vals), VertexAttributeValues::Unorm8x4(t)) => {
+                vals[start..start + t.len()].copy_from_slice(t)
+            }
+            _ => panic!("VertexAttributeValues must have the same variant"),
         }
     }
 
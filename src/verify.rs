@@ -0,0 +1,635 @@
+//! This module contains [`MeshMD::verify`], a consistency checker for a mesh and its metadata.
+//! It is meant for diagnosing a mesh that went wrong after being updated (missing faces,
+//! vertices from the wrong voxel...).
+use crate::prelude::*;
+use bevy::mesh::VertexAttributeValues;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_resource::VertexFormat;
+
+/// A problem found by [`MeshMD::verify`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshIssue {
+    /// The mesh couldn't be read, or the grid couldn't be meshed, so some checks were skipped.
+    Error(MeshemError),
+    /// The mesh isn't made out of quads, 4 vertices and 6 indices (2 triangles) each.
+    NotQuads { vertices: usize, indices: usize },
+    /// A triangle uses vertices outside of the mesh, or from more than one quad.
+    BadTriangle { triangle: usize },
+    /// The metadata has a quad that isn't in the mesh.
+    QuadOutOfBounds {
+        voxel_index: usize,
+        face: Face,
+        vertex: usize,
+    },
+    /// The reverse map of the metadata (vertex to voxel) doesn't agree with its quads.
+    MapMismatch { vertex: usize },
+    /// Vertices of the mesh that aren't a part of any quad in the metadata.
+    UnownedVertices { count: usize },
+    /// The normals of the quad don't point in the direction of its face.
+    WrongNormal { voxel_index: usize, face: Face },
+    /// The quad isn't on its face of the voxel.
+    WrongPosition { voxel_index: usize, face: Face },
+    /// A fresh mesh of the grid has this quad, but the mesh doesn't.
+    MissingQuad { voxel_index: usize, face: Face },
+    /// The mesh has this quad, but a fresh mesh of the grid doesn't.
+    ExtraQuad { voxel_index: usize, face: Face },
+    /// The quad is in both meshes, but the values of an attribute are different.
+    AttributeMismatch {
+        voxel_index: usize,
+        face: Face,
+        attribute: &'static str,
+    },
+    /// The quad is in both meshes, but its triangles are different.
+    TrianglesMismatch { voxel_index: usize, face: Face },
+}
+
+/// The result of [`MeshMD::verify`].
+#[derive(Debug, Clone, Default)]
+pub struct MeshReport {
+    /// Everything that was found wrong, empty if the mesh and the metadata are consistent.
+    pub issues: Vec<MeshIssue>,
+}
+
+impl MeshReport {
+    /// True if no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl<T> MeshMD<T> {
+    /// Check that the mesh and its metadata are consistent with each other and with the grid:
+    /// - Every quad in the metadata is in the mesh, its normals point in the direction of its
+    ///   face, and it is on that face of its voxel. The reverse map agrees with the quads.
+    /// - The mesh is the same as a fresh mesh of `grid` (generated with the arguments the mesh
+    ///   was generated with), quad by quad, regardless of the order of the vertices in the mesh.
+    ///   If Smooth Lighting is enabled, the colors aren't compared.
+    ///
+    /// `grid` is the current state of the grid, with all the logged changes applied to it (call
+    /// `update_mesh` first). If the edges of the mesh were culled against adjacent chunks, use a
+    /// grid that knows them (like [`GridWithNeighbors`]).
+    /// A mesh generated with [`MeshingAlgorithm::Naive`] won't match after it was updated, since
+    /// updating culls the faces between voxels.
+    pub fn verify(
+        &self,
+        mesh: &Mesh,
+        grid: &impl VoxelGrid<T>,
+        reg: &impl VoxelRegistry<Voxel = T>,
+    ) -> MeshReport {
+        let mut issues = self.check_structure(mesh);
+        self.check_quads(mesh, reg, &mut issues);
+
+        let dims = grid.dims();
+        if dims != self.dims {
+            issues.push(MeshIssue::Error(MeshemError::GridSizeMismatch {
+                expected: self.dims.0 * self.dims.1 * self.dims.2,
+                found: dims.0 * dims.1 * dims.2,
+            }));
+            return MeshReport { issues };
+        }
+        match mesh_voxel_grid(&self.outer_layer, grid, reg, self.meshing_algorithm, None) {
+            Ok((fresh, fresh_md)) => self.compare(mesh, &fresh, &fresh_md, &mut issues),
+            Err(e) => issues.push(MeshIssue::Error(e)),
+        }
+        MeshReport { issues }
+    }
+
+    /// Check that the mesh is made out of quads and that the metadata agrees with it, this
+    /// doesn't look at the values of the vertices, and doesn't need the grid.
+    pub(crate) fn check_structure(&self, mesh: &Mesh) -> Vec<MeshIssue> {
+        let mut issues = vec![];
+        let indices = match indices_of(mesh) {
+            Ok(indices) => indices,
+            Err(e) => return vec![MeshIssue::Error(e)],
+        };
+        let vertices = mesh.count_vertices();
+        if !vertices.is_multiple_of(4) || indices.len() != vertices / 4 * 6 {
+            issues.push(MeshIssue::NotQuads {
+                vertices,
+                indices: indices.len(),
+            });
+        }
+        for (triangle, t) in indices.chunks(3).enumerate() {
+            if t.len() != 3
+                || t.iter().any(|v| *v as usize >= vertices)
+                || t.iter().any(|v| v / 4 != t[0] / 4)
+            {
+                issues.push(MeshIssue::BadTriangle { triangle });
+            }
+        }
+
+        let mut quads = 0;
+        for (voxel_index, voxel_quads) in self.vivi.vivi.iter().enumerate() {
+            for q in voxel_quads {
+                let face = face_from_u32(q & REVERSE_OFFSET_CONST);
+                let vertex = (q & OFFSET_CONST) as usize;
+                quads += 1;
+                if !vertex.is_multiple_of(4) || vertex + 4 > vertices {
+                    issues.push(MeshIssue::QuadOutOfBounds {
+                        voxel_index,
+                        face,
+                        vertex,
+                    });
+                }
+                if self.vivi.map.get(&(vertex as u32))
+                    != Some(&(voxel_index as u32 | face_to_u32(face)))
+                {
+                    issues.push(MeshIssue::MapMismatch { vertex });
+                }
+            }
+        }
+        for (vertex, voxel) in self.vivi.map.iter() {
+            let voxel_index = (voxel & OFFSET_CONST) as usize;
+            let quad = vertex | (voxel & !OFFSET_CONST);
+            if !self
+                .vivi
+                .vivi
+                .get(voxel_index)
+                .is_some_and(|q| q.contains(&quad))
+            {
+                issues.push(MeshIssue::MapMismatch {
+                    vertex: *vertex as usize,
+                });
+            }
+        }
+        if vertices > quads * 4 {
+            issues.push(MeshIssue::UnownedVertices {
+                count: vertices - quads * 4,
+            });
+        }
+        issues
+    }
+
+    // Check the normals and positions of the quads in the metadata.
+    fn check_quads(
+        &self,
+        mesh: &Mesh,
+        reg: &impl VoxelRegistry<Voxel = T>,
+        issues: &mut Vec<MeshIssue>,
+    ) {
+        let positions = match positions_of(mesh) {
+            Ok(positions) => positions,
+            Err(e) => return issues.push(MeshIssue::Error(e)),
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+            _ => None,
+        };
+        let voxel_dims = reg.get_voxel_dimensions();
+        let center = reg.get_center();
+        let eps = 1e-4 * voxel_dims[0].max(voxel_dims[1]).max(voxel_dims[2]);
+
+        for (voxel_index, voxel_quads) in self.vivi.vivi.iter().enumerate() {
+            let cords = three_d_cords_arr(voxel_index, self.dims);
+            // The bounds of the voxel.
+            let min: [f32; 3] = std::array::from_fn(|a| {
+                cords[a] as f32 * voxel_dims[a] + center[a] - voxel_dims[a] / 2.0
+            });
+            let max: [f32; 3] = std::array::from_fn(|a| min[a] + voxel_dims[a]);
+            for q in voxel_quads {
+                let face = face_from_u32(q & REVERSE_OFFSET_CONST);
+                let vertex = (q & OFFSET_CONST) as usize;
+                // Interior quads of shaped voxels can be anywhere inside of the voxel.
+                if vertex + 4 > positions.len() || self.vivi.is_interior(vertex as u32) {
+                    continue;
+                }
+                let normal = face.normal().map(|n| n as f32);
+                let axis = normal.iter().position(|n| *n != 0.0).unwrap();
+                let plane = if normal[axis] > 0.0 {
+                    max[axis]
+                } else {
+                    min[axis]
+                };
+
+                let on_face = positions[vertex..vertex + 4].iter().all(|p| {
+                    (p[axis] - plane).abs() <= eps
+                        && (0..3).all(|a| p[a] >= min[a] - eps && p[a] <= max[a] + eps)
+                });
+                if !on_face {
+                    issues.push(MeshIssue::WrongPosition { voxel_index, face });
+                }
+                if let Some(normals) = normals {
+                    let right_normal = normals[vertex..vertex + 4]
+                        .iter()
+                        .all(|n| (0..3).all(|a| (n[a] - normal[a]).abs() <= 1e-4));
+                    if !right_normal {
+                        issues.push(MeshIssue::WrongNormal { voxel_index, face });
+                    }
+                }
+            }
+        }
+    }
+
+    // Compare the mesh to a fresh mesh of the grid, quad by quad.
+    fn compare(
+        &self,
+        mesh: &Mesh,
+        fresh: &Mesh,
+        fresh_md: &MeshMD<T>,
+        issues: &mut Vec<MeshIssue>,
+    ) {
+        let vertices = mesh.count_vertices();
+        // The attributes to compare, and the size of each of their values in bytes.
+        let mut attributes: Vec<(&'static str, &[u8], &[u8], usize)> = vec![];
+        for (attribute, fresh_vals) in fresh.attributes() {
+            if attribute.id == Mesh::ATTRIBUTE_COLOR.id && self.smooth_lighting_params.is_some() {
+                continue;
+            }
+            let Some(vals) = mesh.attribute(attribute.id) else {
+                issues.push(MeshIssue::Error(MeshemError::MissingAttribute(
+                    attribute.name,
+                )));
+                continue;
+            };
+            let found = VertexFormat::from(vals);
+            if found != attribute.format {
+                issues.push(MeshIssue::Error(MeshemError::UnexpectedAttributeFormat {
+                    attribute: attribute.name,
+                    expected: attribute.format,
+                    found,
+                }));
+                continue;
+            }
+            attributes.push((
+                attribute.name,
+                vals.get_bytes(),
+                fresh_vals.get_bytes(),
+                attribute.format.size() as usize,
+            ));
+        }
+        let positions = attributes
+            .iter()
+            .find(|(name, ..)| *name == Mesh::ATTRIBUTE_POSITION.name)
+            .map(|(_, vals, fresh_vals, size)| (*vals, *fresh_vals, *size));
+        let (Ok(indices), Ok(fresh_indices)) = (indices_of(mesh), indices_of(fresh)) else {
+            return;
+        };
+        let triangles = quad_triangles(indices);
+        let fresh_triangles = quad_triangles(fresh_indices);
+
+        for voxel_index in 0..self.vivi.vivi.len() {
+            // A shaped voxel can have more than one quad facing the same side, they are paired
+            // with the quads of the fresh mesh that are in the same place.
+            let quads = self.quads_of(voxel_index);
+            let fresh_quads = fresh_md.quads_of(voxel_index);
+            for (key, fresh_group) in fresh_quads.iter() {
+                let face = key.0;
+                let mut group = quads.get(key).cloned().unwrap_or_default();
+                for &fresh_quad in fresh_group {
+                    let Some(k) = group
+                        .iter()
+                        .position(|&quad| same_values(&positions, quad, fresh_quad, vertices))
+                        .or(if fresh_group.len() == 1 && group.len() == 1 {
+                            Some(0)
+                        } else {
+                            None
+                        })
+                    else {
+                        issues.push(MeshIssue::MissingQuad { voxel_index, face });
+                        continue;
+                    };
+                    let quad = group.swap_remove(k);
+                    if quad + 4 > vertices {
+                        continue;
+                    }
+                    for (attribute, vals, fresh_vals, size) in attributes.iter() {
+                        if vals[quad * size..(quad + 4) * size]
+                            != fresh_vals[fresh_quad * size..(fresh_quad + 4) * size]
+                        {
+                            issues.push(MeshIssue::AttributeMismatch {
+                                voxel_index,
+                                face,
+                                attribute,
+                            });
+                        }
+                    }
+                    if triangles.get(&(quad as u32)) != fresh_triangles.get(&(fresh_quad as u32)) {
+                        issues.push(MeshIssue::TrianglesMismatch { voxel_index, face });
+                    }
+                }
+                for _ in group {
+                    issues.push(MeshIssue::ExtraQuad { voxel_index, face });
+                }
+            }
+            for (key, group) in quads.iter() {
+                if !fresh_quads.contains_key(key) {
+                    for _ in group {
+                        issues.push(MeshIssue::ExtraQuad {
+                            voxel_index,
+                            face: key.0,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // The first vertices of the quads of a voxel, by their face and whether they are interior.
+    fn quads_of(&self, voxel_index: usize) -> HashMap<(Face, bool), Vec<usize>> {
+        let mut r: HashMap<(Face, bool), Vec<usize>> = HashMap::new();
+        for q in self.vivi.vivi[voxel_index].iter() {
+            let vertex = q & OFFSET_CONST;
+            r.entry((
+                face_from_u32(q & REVERSE_OFFSET_CONST),
+                self.vivi.is_interior(vertex),
+            ))
+            .or_default()
+            .push(vertex as usize);
+        }
+        r
+    }
+}
+
+// Whether the quads starting at `a` (in the mesh) and `b` (in the fresh mesh) have the same
+// positions, `positions` are the position bytes of both meshes.
+fn same_values(
+    positions: &Option<(&[u8], &[u8], usize)>,
+    a: usize,
+    b: usize,
+    vertices: usize,
+) -> bool {
+    let Some((vals, fresh_vals, size)) = positions else {
+        return false;
+    };
+    a + 4 <= vertices && vals[a * size..(a + 4) * size] == fresh_vals[b * size..(b + 4) * size]
+}
+
+// The triangles of each quad (by its first vertex), with the vertices relative to the quad. Each
+// triangle is rotated to start from its smallest vertex (keeping its winding), and they are
+// sorted, so two quads with the same triangles in a different order are equal.
+fn quad_triangles(indices: &[u32]) -> HashMap<u32, Vec<[u32; 3]>> {
+    let mut r: HashMap<u32, Vec<[u32; 3]>> = HashMap::new();
+    for t in indices.chunks_exact(3) {
+        let quad = t[0] / 4 * 4;
+        let mut local = [
+            t[0] - quad,
+            t[1].wrapping_sub(quad),
+            t[2].wrapping_sub(quad),
+        ];
+        let min = (0..3).min_by_key(|i| local[*i]).unwrap();
+        local.rotate_left(min);
+        r.entry(quad).or_default().push(local);
+    }
+    for triangles in r.values_mut() {
+        triangles.sort_unstable();
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use bevy::mesh::Indices;
+
+    const DIMS: Dimensions = (4, 3, 4);
+
+    // A floor of stone, with two voxels on it.
+    fn grid() -> Vec<u16> {
+        let mut grid = vec![0u16; 48];
+        grid[..16].fill(1);
+        grid[one_d_cords([1, 1, 1], DIMS)] = 2;
+        grid[one_d_cords([2, 1, 2], DIMS)] = 3;
+        grid
+    }
+
+    fn mesh(grid: &[u16]) -> (Mesh, MeshMD<u16>) {
+        mesh_grid(
+            DIMS,
+            &[],
+            grid,
+            &TestRegistry::new(),
+            MeshingAlgorithm::Culling,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn issues(mesh: &Mesh, md: &MeshMD<u16>, grid: &[u16]) -> Vec<MeshIssue> {
+        md.verify(mesh, &FlatGrid::new(DIMS, grid), &TestRegistry::new())
+            .issues
+    }
+
+    // The first vertex of the quad on `face` of the voxel at `cords`.
+    fn quad(md: &MeshMD<u16>, cords: [usize; 3], face: Face) -> usize {
+        md.vivi.vivi[one_d_cords(cords, DIMS)]
+            .iter()
+            .find(|q| face_from_u32(*q & REVERSE_OFFSET_CONST) == face)
+            .map(|q| (q & OFFSET_CONST) as usize)
+            .unwrap()
+    }
+
+    fn indices_mut(mesh: &mut Mesh) -> &mut Vec<u32> {
+        match mesh.indices_mut() {
+            Some(Indices::U32(indices)) => indices,
+            _ => unreachable!(),
+        }
+    }
+
+    fn float3_mut(mesh: &mut Mesh, attribute: MeshVertexAttribute) -> &mut Vec<[f32; 3]> {
+        match mesh.attribute_mut(attribute) {
+            Some(VertexAttributeValues::Float32x3(vals)) => vals,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn fresh_and_updated_meshes_are_ok() {
+        let mut grid = grid();
+        let (mut mesh, mut md) = mesh(&grid);
+        assert_eq!(issues(&mesh, &md, &grid), []);
+
+        let reg = TestRegistry::new();
+        for (cords, voxel) in [
+            ([3, 1, 0], 2),
+            ([1, 1, 1], 0),
+            ([2, 1, 2], 1),
+            ([0, 0, 0], 0),
+        ] {
+            let index = one_d_cords(cords, DIMS);
+            let (change, logged) = match (grid[index], voxel) {
+                (0, new) => (VoxelChange::Added, new),
+                (old, 0) => (VoxelChange::Broken, old),
+                (old, new) => (VoxelChange::Replaced { old }, new),
+            };
+            grid[index] = voxel;
+            md.log(change, index, logged, neighbors_of(&grid, DIMS, index));
+        }
+        update_mesh(&mut mesh, &mut md, &reg);
+        assert_eq!(issues(&mesh, &md, &grid), []);
+    }
+
+    #[test]
+    fn broken_meshes_are_errors() {
+        let grid = grid();
+        let (mut mesh, md) = mesh(&grid);
+        assert_eq!(
+            md.verify(
+                &mesh,
+                &FlatGrid::new((4, 4, 4), &[0u16; 64]),
+                &TestRegistry::new()
+            )
+            .issues,
+            [MeshIssue::Error(MeshemError::GridSizeMismatch {
+                expected: 48,
+                found: 64
+            })]
+        );
+        mesh.remove_indices();
+        assert_eq!(
+            md.check_structure(&mesh),
+            [MeshIssue::Error(MeshemError::MissingIndices)]
+        );
+    }
+
+    #[test]
+    fn triangles_must_make_quads() {
+        let grid = grid();
+        let (mut mesh, md) = mesh(&grid);
+        let vertices = mesh.count_vertices();
+        indices_mut(&mut mesh).truncate(vertices / 4 * 6 - 3);
+        assert_eq!(
+            issues(&mesh, &md, &grid)[0],
+            MeshIssue::NotQuads {
+                vertices,
+                indices: vertices / 4 * 6 - 3
+            }
+        );
+
+        let (mut mesh, md) = self::mesh(&grid);
+        // A vertex outside of the mesh, and a triangle across two quads.
+        indices_mut(&mut mesh)[2] = vertices as u32;
+        indices_mut(&mut mesh)[4] = 4;
+        let issues = issues(&mesh, &md, &grid);
+        assert!(issues.contains(&MeshIssue::BadTriangle { triangle: 0 }));
+        assert!(issues.contains(&MeshIssue::BadTriangle { triangle: 1 }));
+    }
+
+    #[test]
+    fn quads_must_agree_with_the_map() {
+        let grid = grid();
+        let (mesh, mut md) = mesh(&grid);
+        let vertices = mesh.count_vertices();
+        let index = one_d_cords([1, 1, 1], DIMS);
+        md.vivi.vivi[index][0] = vertices as u32 | (md.vivi.vivi[index][0] & REVERSE_OFFSET_CONST);
+        let face = face_from_u32(md.vivi.vivi[index][0] & REVERSE_OFFSET_CONST);
+        let issues = issues(&mesh, &md, &grid);
+        assert!(issues.contains(&MeshIssue::QuadOutOfBounds {
+            voxel_index: index,
+            face,
+            vertex: vertices
+        }));
+        assert!(issues.contains(&MeshIssue::MapMismatch { vertex: vertices }));
+
+        let (mesh, mut md) = self::mesh(&grid);
+        let vertex = quad(&md, [1, 1, 1], Top) as u32;
+        md.vivi
+            .map
+            .insert(vertex, index as u32 | face_to_u32(Bottom));
+        assert!(
+            self::issues(&mesh, &md, &grid).contains(&MeshIssue::MapMismatch {
+                vertex: vertex as usize
+            })
+        );
+    }
+
+    #[test]
+    fn every_vertex_is_owned() {
+        let grid = grid();
+        let (mut mesh, md) = mesh(&grid);
+        let vertices = mesh.count_vertices();
+        let copy = |vals: &mut VertexAttributeValues| match vals {
+            VertexAttributeValues::Float32x2(v) => v.extend_from_within(..4),
+            VertexAttributeValues::Float32x3(v) => v.extend_from_within(..4),
+            VertexAttributeValues::Float32x4(v) => v.extend_from_within(..4),
+            _ => unreachable!(),
+        };
+        for attribute in TestRegistry::new().all_attributes() {
+            copy(mesh.attribute_mut(attribute).unwrap());
+        }
+        let v = vertices as u32;
+        indices_mut(&mut mesh).extend([v, v + 1, v + 2, v, v + 2, v + 3]);
+        assert_eq!(
+            issues(&mesh, &md, &grid),
+            [MeshIssue::UnownedVertices { count: 4 }]
+        );
+    }
+
+    #[test]
+    fn quads_must_be_on_their_face() {
+        let grid = grid();
+        let (mut mesh, md) = mesh(&grid);
+        let index = one_d_cords([1, 1, 1], DIMS);
+        let top = quad(&md, [1, 1, 1], Top);
+        float3_mut(&mut mesh, Mesh::ATTRIBUTE_NORMAL)[top][1] = -1.0;
+        let right = quad(&md, [1, 1, 1], Right);
+        for p in float3_mut(&mut mesh, Mesh::ATTRIBUTE_POSITION)[right..right + 4].iter_mut() {
+            p[0] += 0.5;
+        }
+        let issues = issues(&mesh, &md, &grid);
+        assert!(issues.contains(&MeshIssue::WrongNormal {
+            voxel_index: index,
+            face: Top
+        }));
+        assert!(issues.contains(&MeshIssue::WrongPosition {
+            voxel_index: index,
+            face: Right
+        }));
+    }
+
+    #[test]
+    fn mesh_must_match_the_grid() {
+        let mut grid = grid();
+        let (mesh, md) = mesh(&grid);
+        // A voxel that isn't in the mesh, and one that is in the mesh but not in the grid.
+        let added = one_d_cords([3, 2, 3], DIMS);
+        let broken = one_d_cords([2, 1, 2], DIMS);
+        grid[added] = 1;
+        grid[broken] = 0;
+        let issues = issues(&mesh, &md, &grid);
+        for face in [Top, Bottom, Right, Left, Back, Forward] {
+            assert!(issues.contains(&MeshIssue::MissingQuad {
+                voxel_index: added,
+                face
+            }));
+        }
+        assert!(issues.contains(&MeshIssue::ExtraQuad {
+            voxel_index: broken,
+            face: Top
+        }));
+        // The floor under the broken voxel has a top face again.
+        assert!(issues.contains(&MeshIssue::MissingQuad {
+            voxel_index: one_d_cords([2, 0, 2], DIMS),
+            face: Top
+        }));
+    }
+
+    #[test]
+    fn quads_must_match_the_fresh_mesh() {
+        let grid = grid();
+        let (mut mesh, md) = mesh(&grid);
+        let index = one_d_cords([1, 1, 1], DIMS);
+        let top = quad(&md, [1, 1, 1], Top);
+        match mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs[top][0] += 0.25,
+            _ => unreachable!(),
+        }
+        // Flip the winding of the first triangle of the back quad.
+        let back = quad(&md, [1, 1, 1], Back) as u32;
+        let indices = indices_mut(&mut mesh);
+        let t = indices.iter().position(|i| i / 4 * 4 == back).unwrap() / 3 * 3;
+        indices.swap(t + 1, t + 2);
+        let issues = issues(&mesh, &md, &grid);
+        assert_eq!(issues.len(), 2);
+        assert!(issues.contains(&MeshIssue::AttributeMismatch {
+            voxel_index: index,
+            face: Top,
+            attribute: Mesh::ATTRIBUTE_UV_0.name
+        }));
+        assert!(issues.contains(&MeshIssue::TrianglesMismatch {
+            voxel_index: index,
+            face: Back
+        }));
+    }
+}
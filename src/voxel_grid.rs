@@ -0,0 +1,208 @@
+//! This module contains the [`VoxelGrid`] trait, it lets the meshing functions read voxels from
+//! any storage (palette-compressed, octrees, Morton-ordered arrays...) without flattening it into
+//! a `Vec<T>` first.
+use crate::prelude::*;
+
+/// Implementing this trait for your own data-structure lets you mesh it directly.
+/// (x, y, z) coordinates are used throughout, y is up, same as in [`Dimensions`].
+pub trait VoxelGrid<T> {
+    /// The dimensions of the grid (width, height, length).
+    fn dims(&self) -> Dimensions;
+    /// The voxel at `cords` ([x, y, z]). `cords` are always within the dimensions of the grid.
+    fn get(&self, cords: [usize; 3]) -> T;
+    /// The voxel at `cords` that are outside of the grid, the meshing functions will only ask for
+    /// voxels that are one step away from the grid. Return None if the voxel isn't known (the
+    /// default), in which case the edge is treated according to the `outer_layer` argument.
+    fn get_padding(&self, _cords: [i32; 3]) -> Option<T> {
+        None
+    }
+    /// The voxel at `cords`, whether it's inside the grid or in its padding.
+    fn get_signed(&self, cords: [i32; 3]) -> Option<T> {
+        let dims = self.dims();
+        if cords[0] >= 0
+            && cords[1] >= 0
+            && cords[2] >= 0
+            && (cords[0] as usize) < dims.0
+            && (cords[1] as usize) < dims.1
+            && (cords[2] as usize) < dims.2
+        {
+            Some(self.get([cords[0] as usize, cords[1] as usize, cords[2] as usize]))
+        } else {
+            self.get_padding(cords)
+        }
+    }
+    /// The 6 neighbors of the voxel at `cords`, in the same order as the neighbors expected by
+    /// [`MeshMD::log`]. None if the neighbor is outside of the grid and isn't known.
+    fn get_neighbors(&self, cords: [usize; 3]) -> [Option<T>; 6] {
+        let [x, y, z] = [cords[0] as i32, cords[1] as i32, cords[2] as i32];
+        [
+            self.get_signed([x, y + 1, z]),
+            self.get_signed([x, y - 1, z]),
+            self.get_signed([x + 1, y, z]),
+            self.get_signed([x - 1, y, z]),
+            self.get_signed([x, y, z + 1]),
+            self.get_signed([x, y, z - 1]),
+        ]
+    }
+}
+
+/// A [`VoxelGrid`] that can be changed, needed by the functions that edit the grid and its mesh
+/// together (like `edit_region`).
+pub trait VoxelGridMut<T>: VoxelGrid<T> {
+    /// Set the voxel at `cords` ([x, y, z]), returns the voxel that was there before. `cords` are
+    /// always within the dimensions of the grid.
+    fn set(&mut self, cords: [usize; 3], voxel: T) -> T;
+}
+
+/// A grid stored as a one dimensional array, in the layout used across the crate
+/// (see [`one_d_cords`]). `S` can be anything that can be viewed as a slice: `&[T]`, `Vec<T>`,
+/// `[T; N]`, `&mut [T]`...
+pub struct FlatGrid<S> {
+    pub dims: Dimensions,
+    pub voxels: S,
+}
+
+impl<S> FlatGrid<S> {
+    pub fn new(dims: Dimensions, voxels: S) -> Self {
+        FlatGrid { dims, voxels }
+    }
+}
+
+impl<T: Copy, S: AsRef<[T]>> VoxelGrid<T> for FlatGrid<S> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.voxels.as_ref()[one_d_cords(cords, self.dims)]
+    }
+}
+
+impl<T: Copy, S: AsRef<[T]> + AsMut<[T]>> VoxelGridMut<T> for FlatGrid<S> {
+    fn set(&mut self, cords: [usize; 3], voxel: T) -> T {
+        let index = one_d_cords(cords, self.dims);
+        std::mem::replace(&mut self.voxels.as_mut()[index], voxel)
+    }
+}
+
+/// A grid stored as a one dimensional array with a one voxel apron around it (the common voxel
+/// engine convention), the array has the dimensions (w + 2, h + 2, l + 2), and `dims` are the
+/// dimensions of the grid inside the apron, (w, h, l). The apron holds the voxels of the adjacent
+/// chunks (or empty voxels where there are none), so the faces on the edges of the grid are culled
+/// and shadowed correctly the first time it's meshed.
+pub struct PaddedGrid<S> {
+    pub dims: Dimensions,
+    pub voxels: S,
+}
+
+impl<S> PaddedGrid<S> {
+    pub fn new(dims: Dimensions, voxels: S) -> Self {
+        PaddedGrid { dims, voxels }
+    }
+
+    /// The dimensions of the array, including the apron.
+    pub fn padded_dims(&self) -> Dimensions {
+        (self.dims.0 + 2, self.dims.1 + 2, self.dims.2 + 2)
+    }
+}
+
+impl<T: Copy, S: AsRef<[T]>> VoxelGrid<T> for PaddedGrid<S> {
+    fn dims(&self) -> Dimensions {
+        self.dims
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.voxels.as_ref()[one_d_cords(
+            [cords[0] + 1, cords[1] + 1, cords[2] + 1],
+            self.padded_dims(),
+        )]
+    }
+
+    fn get_padding(&self, cords: [i32; 3]) -> Option<T> {
+        let index = one_d_cords_safe(
+            [
+                usize::try_from(cords[0] + 1).ok()?,
+                usize::try_from(cords[1] + 1).ok()?,
+                usize::try_from(cords[2] + 1).ok()?,
+            ],
+            self.padded_dims(),
+        )?;
+        self.voxels.as_ref().get(index).copied()
+    }
+}
+
+/// A grid whose padding is looked up in the adjacent chunks (see [`ChunkNeighbors`]).
+/// Only the horizontal edges have padding, since vertical chunks aren't supported.
+pub struct GridWithNeighbors<'a, G, N> {
+    pub grid: &'a G,
+    pub neighbors: &'a N,
+}
+
+impl<T, G: VoxelGrid<T>, N: ChunkNeighbors<T>> VoxelGrid<T> for GridWithNeighbors<'_, G, N> {
+    fn dims(&self) -> Dimensions {
+        self.grid.dims()
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.grid.get(cords)
+    }
+
+    fn get_padding(&self, cords: [i32; 3]) -> Option<T> {
+        if let Some(voxel) = self.grid.get_padding(cords) {
+            return Some(voxel);
+        }
+        let dims = self.dims();
+        if cords[1] < 0 || cords[1] as usize >= dims.1 {
+            return None;
+        }
+        let change = [
+            cords[0].div_euclid(dims.0 as i32),
+            cords[2].div_euclid(dims.2 as i32),
+        ];
+        let direction = from_cords_change(change)?;
+        let index = one_d_cords_safe(
+            [
+                cords[0].rem_euclid(dims.0 as i32) as usize,
+                cords[1] as usize,
+                cords[2].rem_euclid(dims.2 as i32) as usize,
+            ],
+            dims,
+        )?;
+        self.neighbors.get_voxel(direction, index)
+    }
+}
+
+/// A view of the box of another grid between `min` (inclusive) and `max` (exclusive), without
+/// copying its voxels. The voxels of the grid around the box are the padding of the view, so the
+/// faces on the edges of the box are culled against them.
+pub struct SubGrid<'a, G> {
+    pub grid: &'a G,
+    pub min: [usize; 3],
+    pub max: [usize; 3],
+}
+
+impl<T, G: VoxelGrid<T>> VoxelGrid<T> for SubGrid<'_, G> {
+    fn dims(&self) -> Dimensions {
+        (
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        )
+    }
+
+    fn get(&self, cords: [usize; 3]) -> T {
+        self.grid.get([
+            cords[0] + self.min[0],
+            cords[1] + self.min[1],
+            cords[2] + self.min[2],
+        ])
+    }
+
+    fn get_padding(&self, cords: [i32; 3]) -> Option<T> {
+        self.grid.get_signed([
+            cords[0] + self.min[0] as i32,
+            cords[1] + self.min[1] as i32,
+            cords[2] + self.min[2] as i32,
+        ])
+    }
+}
@@ -1,10 +1,374 @@
 //! A module containing the "default block", it is used in the examples,
-//! it is simple and easy to work with.
+//! it is simple and easy to work with, and the shapes that can be cut out of it (slabs, stairs,
+//! plants...).
 use crate::prelude::*;
 use bevy::asset::RenderAssetUsages;
-use bevy::mesh::Indices;
+use bevy::mesh::{Indices, MeshVertexAttribute, VertexAttributeValues};
 use bevy::prelude::*;
-use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::render_resource::{PrimitiveTopology, VertexFormat};
+use serde::{Deserialize, Serialize};
+
+/// A rotation of the texture of a face, clockwise, as seen from outside of the voxel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UvRotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+/// How the texture of a face is laid on it, see [`transform_voxel_uvs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UvTransform {
+    pub rotation: UvRotation,
+    /// Mirror the texture horizontally, before rotating it.
+    pub mirror: bool,
+}
+
+impl UvTransform {
+    pub fn rotated(rotation: UvRotation) -> Self {
+        UvTransform {
+            rotation,
+            mirror: false,
+        }
+    }
+
+    pub fn mirrored() -> Self {
+        UvTransform {
+            rotation: UvRotation::None,
+            mirror: true,
+        }
+    }
+}
+
+/// Rotate and mirror the textures of the faces of `cube`, a voxel mesh generated with
+/// [`generate_voxel_mesh`] (or `generate_voxel_mesh_array`), for example to lay the bark of a
+/// log along its axis. Only the UVs are changed, the tiles stay the same, so a rotation of 90 or
+/// 270 degrees expects square tiles.
+/// Shapes cut out of the cube with [`generate_shape_mesh`] keep the transforms.
+pub fn transform_voxel_uvs(
+    cube: &mut Mesh,
+    transforms: [(Face, UvTransform); 6],
+) -> Result<(), MeshemError> {
+    let uvs = cube
+        .attribute_mut(Mesh::ATTRIBUTE_UV_0)
+        .ok_or(MeshemError::MissingAttribute(Mesh::ATTRIBUTE_UV_0.name))?;
+    let VertexAttributeValues::Float32x2(uvs) = uvs else {
+        return Err(MeshemError::UnexpectedAttributeFormat {
+            attribute: Mesh::ATTRIBUTE_UV_0.name,
+            expected: VertexFormat::Float32x2,
+            found: VertexFormat::from(&*uvs),
+        });
+    };
+    if uvs.len() != 24 {
+        return Err(MeshemError::UnexpectedVertexCount {
+            expected: 24,
+            found: uvs.len(),
+        });
+    }
+    for (face, transform) in transforms {
+        // The corners of each face go around it counter-clockwise (as seen from outside), and
+        // the first edge is along the u axis of the texture.
+        let first = face as usize * 4;
+        if transform.mirror {
+            uvs.swap(first, first + 1);
+            uvs.swap(first + 2, first + 3);
+        }
+        rotate_quad_uvs(
+            uvs,
+            [first, first + 1, first + 2, first + 3],
+            transform.rotation as usize,
+        );
+    }
+    if cube.attribute(Mesh::ATTRIBUTE_TANGENT).is_some() {
+        generate_voxel_tangents(cube)?;
+    }
+    Ok(())
+}
+
+// Rotate the texture of a quad clockwise by `steps` times 90 degrees, `corners` are its vertices
+// counter-clockwise.
+pub(crate) fn rotate_quad_uvs(uvs: &mut [[f32; 2]], corners: [usize; 4], steps: usize) {
+    let old = corners.map(|c| uvs[c]);
+    for (i, c) in corners.into_iter().enumerate() {
+        uvs[c] = old[(i + steps) % 4];
+    }
+}
+
+/// Which side of the voxel each vertex of a shaped voxel mesh is on, the index of the `Face`
+/// (`Face as u32`), plus [`FACE_TAG_INTERIOR`] if the quad faces that side but isn't on it.
+/// Voxel meshes with this attribute are meshed quad by quad, instead of one quad per face: the
+/// quads on a side of the voxel are culled when the neighbor on that side covers it, and interior
+/// quads are never culled.
+/// The vertices must be sorted by quad, 4 for each quad with the same tag, and the triangles of a
+/// quad can only use its own vertices, like the meshes of [`generate_shape_mesh`].
+/// It's only read from the voxel meshes, don't add it to `VoxelRegistry::all_attributes`.
+pub const ATTRIBUTE_FACE_TAG: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_FaceTag", 988_540_918, VertexFormat::Uint32);
+
+/// Added to the [`ATTRIBUTE_FACE_TAG`] of quads that aren't on a side of the voxel, for example
+/// the top of a bottom slab, or the planes of a plant.
+pub const FACE_TAG_INTERIOR: u32 = 0b1000;
+
+/// The shapes [`generate_shape_mesh`] can cut out of a full voxel. Sizes are relative to the
+/// voxel (1.0 is the whole voxel).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum VoxelShape {
+    /// The bottom half of the voxel, or the top half if `top`.
+    Slab { top: bool },
+    /// A bottom slab, with another quarter of the voxel on top of it against the `back` side
+    /// (Right, Left, Back or Forward).
+    Stairs { back: Face },
+    /// Two diagonal planes crossing in the middle of the voxel, like plants and flowers. The
+    /// planes are textured with the sides of the voxel (Right, Left, Back and Forward).
+    Cross,
+    /// A full height post in the middle of the voxel, like the posts of fences (0.25 wide) and
+    /// walls (0.5 wide).
+    Post { width: f32 },
+    /// A full height wall through the middle of the voxel, along the x axis if `along_x`, along
+    /// the z axis otherwise.
+    Pane { along_x: bool, thickness: f32 },
+    /// A layer on the bottom of the voxel, 1/16 high for carpets.
+    Carpet { height: f32 },
+}
+
+impl VoxelShape {
+    /// Whether the shape covers the whole `side` of the voxel, a helper for
+    /// `VoxelRegistry::is_covering`.
+    pub fn covers(&self, side: Face) -> bool {
+        match *self {
+            VoxelShape::Slab { top } => side == if top { Top } else { Bottom },
+            VoxelShape::Stairs { back } => side == Bottom || side == back,
+            VoxelShape::Carpet { .. } => side == Bottom,
+            VoxelShape::Cross | VoxelShape::Post { .. } | VoxelShape::Pane { .. } => false,
+        }
+    }
+
+    // The boxes the shape is made out of, in coordinates relative to the voxel (0.0 - 1.0).
+    fn boxes(&self) -> Vec<([f32; 3], [f32; 3])> {
+        match *self {
+            VoxelShape::Slab { top: false } => vec![([0.0; 3], [1.0, 0.5, 1.0])],
+            VoxelShape::Slab { top: true } => vec![([0.0, 0.5, 0.0], [1.0; 3])],
+            VoxelShape::Stairs { back } => {
+                // The lower half is split in two, so the faces between the boxes match exactly.
+                let axis = if matches!(back, Right | Left) { 0 } else { 2 };
+                let (front, rear) = match back {
+                    Right | Back => ((0.0, 0.5), (0.5, 1.0)),
+                    _ => ((0.5, 1.0), (0.0, 0.5)),
+                };
+                let part = |(from, to): (f32, f32), y: (f32, f32)| {
+                    let mut min = [0.0, y.0, 0.0];
+                    let mut max = [1.0, y.1, 1.0];
+                    min[axis] = from;
+                    max[axis] = to;
+                    (min, max)
+                };
+                vec![
+                    part(front, (0.0, 0.5)),
+                    part(rear, (0.0, 0.5)),
+                    part(rear, (0.5, 1.0)),
+                ]
+            }
+            VoxelShape::Cross => vec![],
+            VoxelShape::Post { width } => {
+                let a = (1.0 - width) / 2.0;
+                vec![([a, 0.0, a], [1.0 - a, 1.0, 1.0 - a])]
+            }
+            VoxelShape::Pane { along_x, thickness } => {
+                let a = (1.0 - thickness) / 2.0;
+                if along_x {
+                    vec![([0.0, 0.0, a], [1.0, 1.0, 1.0 - a])]
+                } else {
+                    vec![([a, 0.0, 0.0], [1.0 - a, 1.0, 1.0])]
+                }
+            }
+            VoxelShape::Carpet { height } => vec![([0.0; 3], [1.0, height, 1.0])],
+        }
+    }
+
+    // The quads of the shape, relative to the voxel, with the face of the voxel each of them is
+    // textured with, and their tag.
+    fn quads(&self) -> Vec<([[f32; 3]; 4], Face, u32)> {
+        let boxes = self.boxes();
+        let mut quads = vec![];
+        for (k, &(min, max)) in boxes.iter().enumerate() {
+            for f in 0..6 {
+                let face = Face::from(f);
+                let (axis, plane) = box_plane(min, max, face);
+                // A face that is glued to the opposite face of another box is hidden.
+                let hidden = boxes
+                    .iter()
+                    .enumerate()
+                    .any(|(j, &(other_min, other_max))| {
+                        j != k
+                            && box_plane(other_min, other_max, face.opposite()) == (axis, plane)
+                            && (0..3).all(|a| {
+                                a == axis || (min[a] == other_min[a] && max[a] == other_max[a])
+                            })
+                    });
+                if hidden {
+                    continue;
+                }
+                let boundary = plane == if face.normal()[axis] > 0 { 1.0 } else { 0.0 };
+                let tag = f as u32 | if boundary { 0 } else { FACE_TAG_INTERIOR };
+                quads.push((box_quad(min, max, face), face, tag));
+            }
+        }
+        if let VoxelShape::Cross = self {
+            // Both sides of both planes, the corners go from `p` to `q` (x, z), and the quad
+            // faces to the right of that line.
+            let plane = |p: [f32; 2], q: [f32; 2], face: Face| {
+                (
+                    [
+                        [p[0], 1.0, p[1]],
+                        [q[0], 1.0, q[1]],
+                        [q[0], 0.0, q[1]],
+                        [p[0], 0.0, p[1]],
+                    ],
+                    face,
+                    face as u32 | FACE_TAG_INTERIOR,
+                )
+            };
+            quads.push(plane([0.0, 0.0], [1.0, 1.0], Right));
+            quads.push(plane([1.0, 1.0], [0.0, 0.0], Left));
+            quads.push(plane([1.0, 0.0], [0.0, 1.0], Back));
+            quads.push(plane([0.0, 1.0], [1.0, 0.0], Forward));
+        }
+        quads
+    }
+}
+
+// The axis of the face of a box, and the coordinate of its plane on that axis.
+fn box_plane(min: [f32; 3], max: [f32; 3], face: Face) -> (usize, f32) {
+    let normal = face.normal();
+    let axis = normal.iter().position(|n| *n != 0).unwrap();
+    (
+        axis,
+        if normal[axis] > 0 {
+            max[axis]
+        } else {
+            min[axis]
+        },
+    )
+}
+
+// The corners of a face of a box, in the same order as the faces of `generate_voxel_mesh`.
+#[rustfmt::skip]
+fn box_quad(min: [f32; 3], max: [f32; 3], face: Face) -> [[f32; 3]; 4] {
+    let [nx, ny, nz] = min;
+    let [x, y, z] = max;
+    match face {
+        Top => [[nx, y, z], [x, y, z], [x, y, nz], [nx, y, nz]],
+        Bottom => [[nx, ny, nz], [x, ny, nz], [x, ny, z], [nx, ny, z]],
+        Right => [[x, y, nz], [x, y, z], [x, ny, z], [x, ny, nz]],
+        Left => [[nx, ny, nz], [nx, ny, z], [nx, y, z], [nx, y, nz]],
+        Back => [[x, y, z], [nx, y, z], [nx, ny, z], [x, ny, z]],
+        Forward => [[x, ny, nz], [nx, ny, nz], [nx, y, nz], [x, y, nz]],
+    }
+}
+
+/// Cut a [`VoxelShape`] out of `cube`, a full voxel mesh generated with [`generate_voxel_mesh`]
+/// (or `generate_voxel_mesh_array`). Every quad of the shape is textured with the face of the
+/// cube it's parallel to, with the part of the texture it would cover on that face, so the side
+/// of a slab shows half of the side texture. Attributes other than the positions, normals and UVs
+/// are copied from that face.
+/// The mesh has an [`ATTRIBUTE_FACE_TAG`], so only the quads on the sides of the voxel are culled
+/// by its neighbors. Use [`VoxelShape::covers`] for `VoxelRegistry::is_covering`.
+pub fn generate_shape_mesh(shape: VoxelShape, cube: &Mesh) -> Result<Mesh, MeshemError> {
+    let cube_positions = positions_of(cube)?;
+    if cube_positions.len() != 24 {
+        return Err(MeshemError::UnexpectedVertexCount {
+            expected: 24,
+            found: cube_positions.len(),
+        });
+    }
+    let mut min = cube_positions[0];
+    let mut max = cube_positions[0];
+    for p in cube_positions {
+        for a in 0..3 {
+            min[a] = min[a].min(p[a]);
+            max[a] = max[a].max(p[a]);
+        }
+    }
+    let quads = shape.quads();
+
+    let mut positions = Vec::with_capacity(quads.len() * 4);
+    let mut normals = Vec::with_capacity(quads.len() * 4);
+    let mut uvs = Vec::with_capacity(quads.len() * 4);
+    // The vertex of the cube each vertex copies the rest of its attributes from.
+    let mut sources: Vec<u32> = Vec::with_capacity(quads.len() * 4);
+    let mut tags = Vec::with_capacity(quads.len() * 4);
+    let cube_uvs = match cube.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+        _ => None,
+    };
+    for (corners, face, tag) in quads.iter() {
+        let corners =
+            corners.map(|c| Vec3::from(std::array::from_fn(|a| min[a] + c[a] * (max[a] - min[a]))));
+        let normal = (corners[1] - corners[0])
+            .cross(corners[3] - corners[0])
+            .normalize();
+        // The corners of the face of the cube the quad is textured with.
+        let first = *face as usize * 4;
+        let face_corners: [Vec3; 4] =
+            std::array::from_fn(|i| Vec3::from(cube_positions[first + i]));
+        let e1 = face_corners[1] - face_corners[0];
+        let e3 = face_corners[3] - face_corners[0];
+        for corner in corners {
+            positions.push(corner.to_array());
+            normals.push(normal.to_array());
+            if let Some(cube_uvs) = cube_uvs {
+                // Where the corner is on the face, projected onto it.
+                let s = (corner - face_corners[0]).dot(e1) / e1.length_squared();
+                let t = (corner - face_corners[0]).dot(e3) / e3.length_squared();
+                let [u0, v0] = cube_uvs[first];
+                let [u1, v1] = cube_uvs[first + 1];
+                let [u3, v3] = cube_uvs[first + 3];
+                uvs.push([
+                    u0 + s * (u1 - u0) + t * (u3 - u0),
+                    v0 + s * (v1 - v0) + t * (v3 - v0),
+                ]);
+            }
+            sources.push(first as u32);
+            tags.push(*tag);
+        }
+    }
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    for (attribute, vals) in cube.attributes() {
+        if attribute.id == Mesh::ATTRIBUTE_POSITION.id
+            || attribute.id == Mesh::ATTRIBUTE_NORMAL.id
+            || (attribute.id == Mesh::ATTRIBUTE_UV_0.id && cube_uvs.is_some())
+            || attribute.id == ATTRIBUTE_FACE_TAG.id
+        {
+            continue;
+        }
+        mesh.insert_attribute(*attribute, vals.get_needed(&sources));
+    }
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    if cube.attribute(Mesh::ATTRIBUTE_NORMAL).is_some() {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    }
+    if cube_uvs.is_some() {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    }
+    mesh.insert_attribute(ATTRIBUTE_FACE_TAG, tags);
+    // The same triangles as the faces of the cube.
+    mesh.insert_indices(Indices::U32(
+        (0..quads.len() as u32)
+            .flat_map(|q| [0, 1, 3, 2, 3, 1].map(|i| q * 4 + i))
+            .collect(),
+    ));
+    // The quads that cross the voxel don't lie on the face they copied their tangents from.
+    if cube.attribute(Mesh::ATTRIBUTE_TANGENT).is_some() && cube_uvs.is_some() {
+        generate_voxel_tangents(&mut mesh)?;
+    }
+    Ok(mesh)
+}
 
 /// Function that generates the mesh of a voxel.
 pub fn generate_voxel_mesh(
@@ -190,3 +554,81 @@ pub fn generate_voxel_mesh(
 
     cube_mesh
 }
+
+/// Add an `ATTRIBUTE_TANGENT` to a voxel mesh (generated with [`generate_voxel_mesh`], or a
+/// shape cut out of it with [`generate_shape_mesh`]), so it can be used with normal maps (the
+/// `normal_map_texture` of a `StandardMaterial`). The tangents follow the u axis of the texture
+/// on each quad, `transform_voxel_uvs`, `generate_shape_mesh`, `rotate_voxel_mesh` and random UV
+/// rotation keep them in line with the UVs.
+/// Add `Mesh::ATTRIBUTE_TANGENT` to `VoxelRegistry::all_attributes` to mesh them.
+pub fn generate_voxel_tangents(voxel: &mut Mesh) -> Result<(), MeshemError> {
+    let positions = positions_of(voxel)?;
+    let uvs = match voxel.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => uvs,
+        Some(uvs) => {
+            return Err(MeshemError::UnexpectedAttributeFormat {
+                attribute: Mesh::ATTRIBUTE_UV_0.name,
+                expected: VertexFormat::Float32x2,
+                found: VertexFormat::from(uvs),
+            })
+        }
+        None => return Err(MeshemError::MissingAttribute(Mesh::ATTRIBUTE_UV_0.name)),
+    };
+    if positions.len() % 4 != 0 || uvs.len() != positions.len() {
+        return Err(MeshemError::UnexpectedVertexCount {
+            expected: positions.len() / 4 * 4,
+            found: uvs.len(),
+        });
+    }
+    // The vertices of voxel meshes are sorted by quad, 4 for each quad, and the triangles of a
+    // quad are (0, 1, 3) and (2, 3, 1).
+    let mut tangents = Vec::with_capacity(positions.len());
+    for (p, uv) in positions.chunks_exact(4).zip(uvs.chunks_exact(4)) {
+        let tangent = quad_tangent([p[0], p[1], p[3]], [uv[0], uv[1], uv[3]]);
+        tangents.extend([tangent; 4]);
+    }
+    voxel.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+    Ok(())
+}
+
+// The tangent of a quad, from its first triangle (counter-clockwise), the direction the u of
+// the texture grows in, and the handedness of the bitangent (the direction v grows in) in `w`.
+fn quad_tangent(p: [[f32; 3]; 3], uv: [[f32; 2]; 3]) -> [f32; 4] {
+    let [p0, p1, p2] = p.map(Vec3::from);
+    let [uv0, uv1, uv2] = uv.map(Vec2::from);
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let (d1, d2) = (uv1 - uv0, uv2 - uv0);
+    let normal = e1.cross(e2).normalize_or_zero();
+    let det = d1.x * d2.y - d2.x * d1.y;
+    if det.abs() <= f32::EPSILON {
+        // The texture is squashed into a line, any tangent on the quad will do.
+        return e1.normalize_or_zero().extend(1.0).to_array();
+    }
+    let tangent = (e1 * d2.y - e2 * d1.y) / det;
+    let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+    let tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+    let w = if normal.cross(tangent).dot(bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    tangent.extend(w).to_array()
+}
+
+// Turn the tangents of a quad facing `face` along with its texture, after it was rotated
+// clockwise by `steps` times 90 degrees with `rotate_quad_uvs`.
+pub(crate) fn rotate_quad_tangents(
+    tangents: &mut [[f32; 4]],
+    corners: [usize; 4],
+    face: Face,
+    steps: usize,
+) {
+    let normal = Vec3::from(face.normal().map(|n| n as f32));
+    for c in corners {
+        let mut tangent = Vec3::from_slice(&tangents[c]);
+        for _ in 0..steps % 4 {
+            tangent = tangent.cross(normal);
+        }
+        tangents[c] = tangent.extend(tangents[c][3]).to_array();
+    }
+}